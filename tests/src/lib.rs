@@ -1,25 +1,64 @@
 #![cfg(test)]
 
+use std::fs;
+use std::net::TcpListener;
+use std::path::PathBuf;
 use std::thread;
 
+use texla_client::config::ClientConfig;
+use texla_server::config::ServerConfig;
+
+/// Deletes the test server's save file, and the temporary file it's written through, when the
+/// test ends.
+struct SaveFile(PathBuf);
+
+impl Drop for SaveFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+        let _ = fs::remove_file(self.0.with_extension("ron.tmp"));
+    }
+}
+
 #[test]
 fn connect_and_register() {
-    thread::spawn(|| {
-        texla_server::app().run();
+    // Let the OS pick a port so this can't collide with a running server or another test
+    let port = TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let save_file = SaveFile(std::env::temp_dir().join(format!("texla-test-{}.ron", port)));
+    let save_path = save_file.0.clone();
+
+    thread::spawn(move || {
+        texla_server::app(ServerConfig {
+            port,
+            save_path,
+            ..Default::default()
+        })
+        .run();
     });
 
     let (ev_in_tx, ev_in_rx) = std::sync::mpsc::channel();
     let (ev_out_tx, ev_out_rx) = std::sync::mpsc::channel();
 
     thread::spawn(move || {
-        texla_client::run(ev_in_rx, ev_out_tx);
+        texla_client::run(
+            &ClientConfig {
+                url: format!("ws://127.0.0.1:{}/socket", port),
+                ..Default::default()
+            },
+            ev_in_rx,
+            ev_out_tx,
+        );
     });
 
     // Give the server time to start and the client to connect
     thread::sleep(std::time::Duration::from_millis(1000));
 
+    // Passwords are hashed off the main thread, which can take a few ticks
     ev_in_tx.send("register foo | bar".to_owned()).unwrap();
-    thread::sleep(std::time::Duration::from_millis(100));
+    thread::sleep(std::time::Duration::from_millis(500));
 
     assert!(ev_out_rx
         .try_recv()
@@ -0,0 +1,341 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use serde::Deserialize;
+
+use crate::markup::{self, Line};
+
+/// What's written in the automation file, before it's checked.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct AutomationFile {
+    aliases: HashMap<String, String>,
+    triggers: Vec<TriggerFile>,
+    macros: HashMap<String, String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct TriggerFile {
+    pattern: String,
+    #[serde(default)]
+    highlight: Option<String>,
+    #[serde(default)]
+    gag: bool,
+    #[serde(default)]
+    send: Option<String>,
+}
+
+/// Something to do whenever a line of output matches a pattern.
+#[derive(Debug)]
+struct Trigger {
+    pattern: Regex,
+    /// A markup tag, like `red` or `bold`, to show the line in.
+    highlight: Option<String>,
+    /// Hides the line.
+    gag: bool,
+    /// A command to send back, where `$1` or `$name` stand for what the pattern captured.
+    send: Option<String>,
+}
+
+/// The player's aliases, triggers and key macros, loaded from a TOML file like:
+///
+/// ```toml
+/// [aliases]
+/// gn = "go north"
+///
+/// [[triggers]]
+/// pattern = "^(\\w+) says"
+/// highlight = "cyan"
+///
+/// [macros]
+/// F1 = "look"
+/// ```
+#[derive(Debug, Default)]
+pub struct Automation {
+    aliases: HashMap<String, String>,
+    triggers: Vec<Trigger>,
+    /// Commands by the number of the function key they're bound to.
+    macros: HashMap<u8, String>,
+    /// When triggers last sent commands, for the last second.
+    sent: VecDeque<Instant>,
+}
+
+/// How many commands triggers can send a second, so that a trigger set off by its own response
+/// can't flood the server.
+const MAX_TRIGGER_SENDS: usize = 5;
+
+/// What the triggers did with a line of output.
+#[derive(Debug, Default)]
+pub struct Triggered {
+    /// The line as it should be shown, if it should be shown at all.
+    pub line: Option<Line>,
+    /// The commands to send in response.
+    pub sends: Vec<String>,
+    /// How many commands weren't sent for going over [`MAX_TRIGGER_SENDS`].
+    pub dropped: usize,
+}
+
+impl Automation {
+    /// Loads the file at `path`. A file that doesn't exist has nothing in it.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        if !fs::exists(path).unwrap_or(false) {
+            return Ok(Self::default());
+        }
+        let str = fs::read_to_string(path)
+            .map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+        let file = toml::from_str::<AutomationFile>(&str)
+            .map_err(|e| format!("Can't parse {}: {}", path.display(), e))?;
+
+        let triggers = file
+            .triggers
+            .into_iter()
+            .map(|trigger| {
+                if let Some(tag) = trigger.highlight.as_deref() {
+                    if !markup::is_tag(tag) {
+                        return Err(format!("Unknown highlight {} in {}", tag, path.display()));
+                    }
+                }
+                Ok(Trigger {
+                    pattern: Regex::new(&trigger.pattern)
+                        .map_err(|e| format!("Invalid pattern in {}: {}", path.display(), e))?,
+                    highlight: trigger.highlight,
+                    gag: trigger.gag,
+                    send: trigger.send,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let macros = file
+            .macros
+            .into_iter()
+            .map(
+                |(key, command)| match key.strip_prefix('F').map(str::parse) {
+                    Some(Ok(number @ 1..=12)) => Ok((number, command)),
+                    _ => Err(format!(
+                        "Unknown macro key {} in {}, should be F1 to F12",
+                        key,
+                        path.display()
+                    )),
+                },
+            )
+            .collect::<Result<HashMap<_, _>, _>>()?;
+
+        Ok(Self {
+            aliases: file.aliases,
+            triggers,
+            macros,
+            sent: VecDeque::new(),
+        })
+    }
+
+    /// Replaces an alias at the start of a line with what it stands for. Anything typed after the
+    /// alias is kept on the end.
+    pub fn expand(&self, line: &str) -> String {
+        let (word, rest) = line.split_once(' ').unwrap_or((line, ""));
+        match self.aliases.get(word) {
+            Some(expansion) if rest.is_empty() => expansion.clone(),
+            Some(expansion) => format!("{} {}", expansion, rest),
+            None => line.to_owned(),
+        }
+    }
+
+    /// The command bound to function key `number`, if any.
+    pub fn macro_for(&self, number: u8) -> Option<&str> {
+        self.macros.get(&number).map(String::as_str)
+    }
+
+    /// Runs every trigger on a line of output that arrived at `now`.
+    pub fn trigger(&mut self, mut line: Line, now: Instant) -> Triggered {
+        let text = line
+            .iter()
+            .map(|span| span.text.as_str())
+            .collect::<String>();
+        let mut gagged = false;
+        let mut sends = Vec::new();
+        let mut dropped = 0;
+        while self
+            .sent
+            .front()
+            .is_some_and(|sent| now.duration_since(*sent) >= Duration::from_secs(1))
+        {
+            self.sent.pop_front();
+        }
+
+        for trigger in &self.triggers {
+            let Some(captures) = trigger.pattern.captures(&text) else {
+                continue;
+            };
+            gagged |= trigger.gag;
+            if let Some(tag) = &trigger.highlight {
+                for span in &mut line {
+                    span.style = markup::apply(span.style, tag).unwrap_or(span.style);
+                }
+            }
+            if let Some(send) = &trigger.send {
+                if self.sent.len() >= MAX_TRIGGER_SENDS {
+                    dropped += 1;
+                    continue;
+                }
+                let mut response = String::new();
+                captures.expand(send, &mut response);
+                sends.push(response);
+                self.sent.push_back(now);
+            }
+        }
+
+        Triggered {
+            line: (!gagged).then_some(line),
+            sends,
+            dropped,
+        }
+    }
+
+    /// How much there is, like `2 aliases, 1 trigger and 0 macros`.
+    pub fn summary(&self) -> String {
+        let count =
+            |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+        format!(
+            "{}, {} and {}",
+            count(self.aliases.len(), "alias", "aliases"),
+            count(self.triggers.len(), "trigger", "triggers"),
+            count(self.macros.len(), "macro", "macros")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+
+    use crossterm::style::{Color, ContentStyle};
+
+    use crate::automation::{Automation, MAX_TRIGGER_SENDS};
+    use crate::markup::{self, Line};
+
+    fn load(name: &str, toml: &str) -> Result<Automation, String> {
+        let path = std::env::temp_dir().join(format!(
+            "texla-automation-{}-{}.toml",
+            std::process::id(),
+            name
+        ));
+        fs::write(&path, toml).unwrap();
+        let automation = Automation::load(&path);
+        fs::remove_file(&path).unwrap();
+        automation
+    }
+
+    fn line(text: &str) -> Line {
+        markup::parse(text).remove(0)
+    }
+
+    #[test]
+    fn aliases_expand_at_the_start() {
+        let automation = load("aliases", "[aliases]\ngn = \"go north\"\nk = \"kill\"").unwrap();
+
+        assert_eq!(automation.expand("gn"), "go north");
+        assert_eq!(automation.expand("k rat"), "kill rat");
+        assert_eq!(automation.expand("say gn"), "say gn");
+    }
+
+    #[test]
+    fn triggers_highlight_gag_and_send() {
+        let mut automation = load(
+            "triggers",
+            r#"
+            [[triggers]]
+            pattern = "^(\\w+) says"
+            highlight = "cyan"
+
+            [[triggers]]
+            pattern = "^(?P<who>\\w+) waves"
+            send = "wave $who"
+
+            [[triggers]]
+            pattern = "spam"
+            gag = true
+            "#,
+        )
+        .unwrap();
+        let now = Instant::now();
+
+        let triggered = automation.trigger(line("Bob says hi"), now);
+        let style = ContentStyle {
+            foreground_color: Some(Color::Cyan),
+            ..Default::default()
+        };
+        assert!(triggered
+            .line
+            .is_some_and(|line| line.iter().all(|span| span.style == style)));
+        assert!(triggered.sends.is_empty());
+
+        let triggered = automation.trigger(line("Bob waves"), now);
+        assert_eq!(triggered.line, Some(line("Bob waves")));
+        assert_eq!(triggered.sends, vec!["wave Bob"]);
+
+        assert_eq!(automation.trigger(line("more spam"), now).line, None);
+    }
+
+    #[test]
+    fn trigger_sends_are_capped() {
+        let mut automation = load(
+            "capped",
+            "[[triggers]]\npattern = \"ping\"\nsend = \"ping\"",
+        )
+        .unwrap();
+        let now = Instant::now();
+
+        let sent = (0..MAX_TRIGGER_SENDS + 2)
+            .map(|_| automation.trigger(line("ping"), now))
+            .map(|triggered| (triggered.sends.len(), triggered.dropped))
+            .fold((0, 0), |(sent, dropped), (s, d)| (sent + s, dropped + d));
+        assert_eq!(sent, (MAX_TRIGGER_SENDS, 2));
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(automation.trigger(line("ping"), later).sends.len(), 1);
+    }
+
+    #[test]
+    fn macros_are_bound_to_function_keys() {
+        let automation = load("macros", "[macros]\nF1 = \"look\"\nF12 = \"quit\"").unwrap();
+
+        assert_eq!(automation.macro_for(1), Some("look"));
+        assert_eq!(automation.macro_for(12), Some("quit"));
+        assert_eq!(automation.macro_for(2), None);
+    }
+
+    #[test]
+    fn broken_files_are_refused() {
+        assert!(load("bad-key", "[macros]\nF13 = \"look\"").is_err());
+        assert!(load(
+            "bad-tag",
+            "[[triggers]]\npattern = \"x\"\nhighlight = \"pink\""
+        )
+        .is_err());
+        assert!(load("bad-pattern", "[[triggers]]\npattern = \"(\"").is_err());
+        assert!(load("bad-field", "[aliases]\n[extra]").is_err());
+    }
+
+    #[test]
+    fn missing_files_are_empty() {
+        let automation = Automation::load(Path::new("/nonexistent/texla-automation.toml")).unwrap();
+
+        assert_eq!(automation.summary(), "0 aliases, 0 triggers and 0 macros");
+    }
+
+    #[test]
+    fn summary_counts_one_without_plural() {
+        let automation = load(
+            "summary",
+            "[aliases]\ngn = \"go north\"\n[[triggers]]\npattern = \"x\"\n[macros]\nF1 = \"look\"",
+        )
+        .unwrap();
+
+        assert_eq!(automation.summary(), "1 alias, 1 trigger and 1 macro");
+    }
+}
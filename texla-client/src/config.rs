@@ -0,0 +1,335 @@
+use std::path::PathBuf;
+use std::{fmt, fs};
+use std::time::Duration;
+
+use serde::Deserialize;
+
+/// Where the client connects and how hard it tries. Built from, in increasing order of
+/// precedence, the defaults, a TOML config file, `TEXLA_*` environment variables, and command
+/// line arguments.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientConfig {
+    /// A `ws://` or `wss://` URL.
+    pub url: String,
+    /// How many times to try connecting before giving up, or 0 to keep trying forever.
+    pub max_attempts: u32,
+    /// How long to wait after the first failed attempt. Doubles after each failure after that.
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// How many lines of output are kept to scroll back through.
+    pub scrollback_lines: usize,
+    /// Whether to show the colors and styles the server sends.
+    pub color: bool,
+    /// Where typed commands are remembered between sessions. Defaults to `.texla_history` in the
+    /// home directory.
+    pub history_file: Option<PathBuf>,
+    /// How many typed commands to remember between sessions, or 0 to not remember any.
+    pub history_lines: usize,
+    /// Where to write a log of each session, if anywhere.
+    pub log_dir: Option<PathBuf>,
+    /// Where aliases, triggers and key macros are read from, at startup and on `/reload`.
+    pub automation_file: PathBuf,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            url: "ws://localhost:8080/socket".to_owned(),
+            max_attempts: 10,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            scrollback_lines: 5000,
+            color: true,
+            history_file: None,
+            history_lines: 1000,
+            log_dir: None,
+            automation_file: PathBuf::from("texla-automation.toml"),
+        }
+    }
+}
+
+const DEFAULT_CONFIG_PATH: &str = "texla-client.toml";
+
+/// Why the client shouldn't start with the arguments it was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `--help` was asked for, so [`USAGE`] should be shown instead.
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::Invalid(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<String> for ConfigError {
+    fn from(err: String) -> Self {
+        ConfigError::Invalid(err)
+    }
+}
+
+pub const USAGE: &str = "Usage: texla-client [options] [url]
+
+Options:
+  --config <path>              Config file to read (default: texla-client.toml, if it exists)
+  --url <url>                  Server to connect to, like ws://localhost:8080/socket
+  --max-attempts <n>           Connection attempts before giving up, or 0 for no limit
+  --initial-backoff-ms <ms>    Delay after the first failed attempt
+  --max-backoff-ms <ms>        Longest delay between attempts
+  --scrollback-lines <n>       Lines of output kept to scroll back through
+  --color <true|false>         Whether to show the server's colors and styles
+  --history-file <path>        Where typed commands are remembered (default: ~/.texla_history)
+  --history-lines <n>          Typed commands to remember, or 0 for none
+  --log-dir <path>             Directory to write a log of each session to
+  --automation-file <path>     Aliases, triggers and macros to load
+                               (default: texla-automation.toml)
+  --help                       Show this message
+
+Every option can also be set with an environment variable, like TEXLA_URL.";
+
+/// Every option, as `(flag, environment variable)`.
+const OPTIONS: [(&str, &str); 11] = [
+    ("--config", "TEXLA_CLIENT_CONFIG"),
+    ("--url", "TEXLA_URL"),
+    ("--max-attempts", "TEXLA_MAX_ATTEMPTS"),
+    ("--initial-backoff-ms", "TEXLA_INITIAL_BACKOFF_MS"),
+    ("--max-backoff-ms", "TEXLA_MAX_BACKOFF_MS"),
+    ("--scrollback-lines", "TEXLA_SCROLLBACK_LINES"),
+    ("--color", "TEXLA_COLOR"),
+    ("--history-file", "TEXLA_HISTORY_FILE"),
+    ("--history-lines", "TEXLA_HISTORY_LINES"),
+    ("--log-dir", "TEXLA_LOG_DIR"),
+    ("--automation-file", "TEXLA_AUTOMATION_FILE"),
+];
+
+impl ClientConfig {
+    /// Loads the config from `args` (without the program name) and the environment as seen
+    /// through `env`. A lone argument that isn't a flag is taken as the URL.
+    pub fn load(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let mut flags = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Err(ConfigError::Help);
+            }
+            if !arg.starts_with("-") {
+                flags.push(("--url".to_owned(), arg));
+                continue;
+            }
+            if !OPTIONS.iter().any(|(flag, _)| *flag == arg) {
+                return Err(format!("Unknown option: {}\n\n{}", arg, USAGE).into());
+            }
+            let Some(value) = args.next() else {
+                return Err(format!("Missing value for {}", arg).into());
+            };
+            flags.push((arg, value));
+        }
+
+        let flag_path = flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| flag == "--config")
+            .map(|(_, value)| value.clone());
+        let mut config = match flag_path.or_else(|| env("TEXLA_CLIENT_CONFIG")) {
+            Some(path) => Self::from_file(&path)?,
+            None if fs::exists(DEFAULT_CONFIG_PATH).unwrap_or(false) => {
+                Self::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            None => Self::default(),
+        };
+
+        for (flag, var) in OPTIONS {
+            if let Some(value) = env(var) {
+                config.set(flag, value)?;
+            }
+        }
+        for (flag, value) in flags {
+            config.set(&flag, value)?;
+        }
+
+        if config.history_file.is_none() {
+            config.history_file = env("HOME")
+                .or_else(|| env("USERPROFILE"))
+                .map(|home| PathBuf::from(home).join(".texla_history"));
+        }
+
+        if !config.url.starts_with("ws://") && !config.url.starts_with("wss://") {
+            return Err(format!(
+                "Invalid URL {}: must start with ws:// or wss://",
+                config.url
+            )
+            .into());
+        }
+
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Self, String> {
+        let str = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        toml::from_str(&str).map_err(|e| format!("Can't parse {}: {}", path, e))
+    }
+
+    fn set(&mut self, flag: &str, value: String) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+        }
+
+        match flag {
+            "--config" => {}
+            "--url" => self.url = value,
+            "--max-attempts" => self.max_attempts = parse(flag, &value)?,
+            "--initial-backoff-ms" => self.initial_backoff_ms = parse(flag, &value)?,
+            "--max-backoff-ms" => self.max_backoff_ms = parse(flag, &value)?,
+            "--scrollback-lines" => self.scrollback_lines = parse(flag, &value)?,
+            "--color" => self.color = parse(flag, &value)?,
+            "--history-file" => self.history_file = Some(PathBuf::from(value)),
+            "--history-lines" => self.history_lines = parse(flag, &value)?,
+            "--log-dir" => self.log_dir = Some(PathBuf::from(value)),
+            "--automation-file" => self.automation_file = PathBuf::from(value),
+            _ => unreachable!("{} is not an option", flag),
+        }
+        Ok(())
+    }
+
+    /// How long to wait after the given failed attempt, counting from 1.
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u64.saturating_pow(attempt.saturating_sub(1));
+        Duration::from_millis(
+            self.initial_backoff_ms
+                .saturating_mul(factor)
+                .min(self.max_backoff_ms),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+
+    use crate::config::{ClientConfig, ConfigError};
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<ClientConfig, ConfigError> {
+        let env = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        ClientConfig::load(args.iter().map(|s| s.to_string()), |key| {
+            env.get(key).cloned()
+        })
+    }
+
+    #[test]
+    fn defaults_are_used_without_options() {
+        assert_eq!(load(&[], &[]), Ok(ClientConfig::default()));
+    }
+
+    #[test]
+    fn flags_override_env() {
+        let config = load(
+            &["--max-attempts", "3"],
+            &[("TEXLA_MAX_ATTEMPTS", "5"), ("TEXLA_COLOR", "false")],
+        )
+        .unwrap();
+
+        assert_eq!(config.max_attempts, 3);
+        assert!(!config.color);
+    }
+
+    #[test]
+    fn lone_argument_is_the_url() {
+        let config = load(
+            &["wss://example.com/socket"],
+            &[("TEXLA_URL", "ws://localhost:9000/socket")],
+        )
+        .unwrap();
+
+        assert_eq!(config.url, "wss://example.com/socket");
+    }
+
+    #[test]
+    fn file_is_overridden_by_env_and_flags() {
+        let path = std::env::temp_dir().join(format!(
+            "texla-client-config-{}.toml",
+            std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "url = \"ws://example.com/socket\"\nmax_attempts = 1\nscrollback_lines = 10\n",
+        )
+        .unwrap();
+
+        let config = load(
+            &["--config", path.to_str().unwrap(), "--scrollback-lines", "20"],
+            &[("TEXLA_MAX_ATTEMPTS", "2")],
+        );
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.url, "ws://example.com/socket");
+        assert_eq!(config.max_attempts, 2);
+        assert_eq!(config.scrollback_lines, 20);
+    }
+
+    #[test]
+    fn history_file_defaults_to_home() {
+        let config = load(&[], &[("HOME", "/home/alice")]).unwrap();
+
+        assert_eq!(
+            config.history_file.as_deref(),
+            Some(std::path::Path::new("/home/alice/.texla_history"))
+        );
+    }
+
+    #[test]
+    fn urls_must_be_websockets() {
+        assert!(load(&["http://example.com"], &[]).is_err());
+        assert!(load(&["localhost:8080"], &[]).is_err());
+        assert!(load(&[], &[("TEXLA_URL", "ftp://example.com")]).is_err());
+        assert!(load(&["ws://example.com"], &[]).is_ok());
+        assert!(load(&["wss://example.com"], &[]).is_ok());
+    }
+
+    #[test]
+    fn invalid_values_fail() {
+        assert!(load(&["--max-attempts", "lots"], &[]).is_err());
+        assert!(load(&["--max-attempts"], &[]).is_err());
+        assert!(load(&["--frobnicate", "yes"], &[]).is_err());
+        assert!(load(&[], &[("TEXLA_COLOR", "sometimes")]).is_err());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_limit() {
+        let config = ClientConfig {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 3000,
+            ..ClientConfig::default()
+        };
+
+        assert_eq!(config.backoff(1), Duration::from_millis(500));
+        assert_eq!(config.backoff(2), Duration::from_millis(1000));
+        assert_eq!(config.backoff(3), Duration::from_millis(2000));
+        assert_eq!(config.backoff(4), Duration::from_millis(3000));
+        assert_eq!(config.backoff(u32::MAX), Duration::from_millis(3000));
+    }
+
+    #[test]
+    fn help_is_not_invalid() {
+        assert_eq!(
+            load(&["--max-attempts", "1", "--help"], &[]),
+            Err(ConfigError::Help)
+        );
+        assert_eq!(load(&["-h"], &[]), Err(ConfigError::Help));
+    }
+}
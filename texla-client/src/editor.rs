@@ -0,0 +1,220 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// The line being typed, with a cursor that moves a grapheme at a time.
+#[derive(Debug, Default, Clone)]
+pub struct LineEditor {
+    text: String,
+    /// A byte offset into `text`, always at the start of a grapheme.
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Replaces the line, leaving the cursor at the end.
+    pub fn set(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    /// Empties the line, returning what was on it.
+    pub fn take(&mut self) -> String {
+        self.cursor = 0;
+        std::mem::take(&mut self.text)
+    }
+
+    pub fn insert(&mut self, c: char) {
+        self.text.insert(self.cursor, c);
+        self.cursor += c.len_utf8();
+    }
+
+    pub fn backspace(&mut self) {
+        let start = self.prev_boundary();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    pub fn delete(&mut self) {
+        let end = self.next_boundary();
+        self.text.replace_range(self.cursor..end, "");
+    }
+
+    pub fn left(&mut self) {
+        self.cursor = self.prev_boundary();
+    }
+
+    pub fn right(&mut self) {
+        self.cursor = self.next_boundary();
+    }
+
+    pub fn home(&mut self) {
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self) {
+        self.cursor = self.text.len();
+    }
+
+    /// Moves to the start of the word the cursor's in, or the one before it.
+    pub fn word_left(&mut self) {
+        self.cursor = self.word_start();
+    }
+
+    /// Moves to the end of the word the cursor's in, or the one after it.
+    pub fn word_right(&mut self) {
+        let after = &self.text[self.cursor..];
+        let rest = after.trim_start();
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        self.cursor += after.len() - rest.len() + end;
+    }
+
+    /// Deletes back to the start of the word, like Ctrl+W in a shell.
+    pub fn kill_word(&mut self) {
+        let start = self.word_start();
+        self.text.replace_range(start..self.cursor, "");
+        self.cursor = start;
+    }
+
+    /// Deletes everything before the cursor, like Ctrl+U in a shell.
+    pub fn kill_to_start(&mut self) {
+        self.text.replace_range(..self.cursor, "");
+        self.cursor = 0;
+    }
+
+    /// The line as it should be shown, with everything from byte `secret` on starred out, and how
+    /// many terminal columns the text before the cursor takes up.
+    pub fn display(&self, secret: Option<usize>) -> (String, usize) {
+        let Some(secret) = secret.filter(|secret| *secret < self.text.len()) else {
+            return (self.text.clone(), self.text[..self.cursor].width());
+        };
+
+        let stars = |text: &str| "*".repeat(text.graphemes(true).count());
+        let shown = self.text[..secret].to_owned() + &stars(&self.text[secret..]);
+        let column = if self.cursor <= secret {
+            self.text[..self.cursor].width()
+        } else {
+            self.text[..secret].width() + stars(&self.text[secret..self.cursor]).len()
+        };
+        (shown, column)
+    }
+
+    fn prev_boundary(&self) -> usize {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    fn next_boundary(&self) -> usize {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cursor, |g| self.cursor + g.len())
+    }
+
+    fn word_start(&self) -> usize {
+        let before = self.text[..self.cursor].trim_end();
+        before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| c.is_whitespace())
+            .map_or(0, |(i, c)| i + c.len_utf8())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::editor::LineEditor;
+
+    fn typed(text: &str) -> LineEditor {
+        let mut editor = LineEditor::default();
+        editor.set(text.to_owned());
+        editor
+    }
+
+    fn typed_at_start(text: &str) -> LineEditor {
+        let mut editor = typed(text);
+        editor.home();
+        editor
+    }
+
+    #[test]
+    fn combining_graphemes_are_edited_whole() {
+        // "e" followed by a combining acute accent
+        let mut editor = typed("cafe\u{301}");
+        editor.backspace();
+        assert_eq!(editor.text(), "caf");
+
+        let mut editor = typed_at_start("e\u{301}x");
+        editor.delete();
+        assert_eq!(editor.text(), "x");
+
+        let mut editor = typed_at_start("e\u{301}x");
+        editor.right();
+        editor.insert('!');
+        assert_eq!(editor.text(), "e\u{301}!x");
+    }
+
+    #[test]
+    fn wide_graphemes_are_edited_whole() {
+        let mut editor = typed("日本");
+        editor.left();
+        editor.insert('の');
+        assert_eq!(editor.text(), "日の本");
+        editor.backspace();
+        editor.backspace();
+        assert_eq!(editor.text(), "本");
+    }
+
+    #[test]
+    fn words_are_moved_over() {
+        let mut editor = typed("go  north now");
+        editor.word_left();
+        editor.insert('|');
+        assert_eq!(editor.text(), "go  north |now");
+
+        editor.home();
+        editor.word_right();
+        editor.insert('|');
+        assert_eq!(editor.text(), "go|  north |now");
+        editor.word_right();
+        editor.insert('|');
+        assert_eq!(editor.text(), "go|  north| |now");
+    }
+
+    #[test]
+    fn words_and_lines_are_killed() {
+        let mut editor = typed("say hello  ");
+        editor.kill_word();
+        assert_eq!(editor.text(), "say ");
+        editor.kill_word();
+        assert_eq!(editor.text(), "");
+
+        let mut editor = typed("say hello");
+        editor.word_left();
+        editor.kill_to_start();
+        assert_eq!(editor.text(), "hello");
+        editor.insert('>');
+        assert_eq!(editor.text(), ">hello");
+    }
+
+    #[test]
+    fn display_columns_count_terminal_cells() {
+        let mut editor = typed("日本");
+        assert_eq!(editor.display(None), ("日本".to_owned(), 4));
+        editor.left();
+        assert_eq!(editor.display(None).1, 2);
+
+        let mut editor = typed("login a|pässwörd");
+        assert_eq!(editor.display(Some(8)), ("login a|********".to_owned(), 16));
+        editor.home();
+        editor.word_right();
+        assert_eq!(editor.display(Some(8)).1, 5);
+        editor.end();
+        editor.left();
+        assert_eq!(editor.display(Some(8)).1, 15);
+    }
+}
@@ -0,0 +1,306 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Commands whose arguments are credentials. Anything the server could take as one of them,
+/// abbreviations included, is redacted.
+const CREDENTIAL_COMMANDS: [&str; 3] = ["login", "register", "resume"];
+
+/// The server's other commands that can be used before logging in. The credential commands can
+/// only be used then, so these are the ones their abbreviations have to be told apart from.
+const LOGGED_OUT_COMMANDS: [&str; 3] = ["help", "protocol", "echo"];
+
+/// The server's aliases that start like a credential command. The server takes a whole alias over
+/// an abbreviation.
+const SHADOWING_ALIASES: [&str; 1] = ["l"];
+
+/// Whether the server would take `command` as a credential command, by its name or as the start
+/// of nothing else.
+fn is_credential_command(command: &str) -> bool {
+    if command.is_empty() || SHADOWING_ALIASES.contains(&command) {
+        return false;
+    }
+    match CREDENTIAL_COMMANDS
+        .iter()
+        .chain(&LOGGED_OUT_COMMANDS)
+        .copied()
+        .filter(|name| name.starts_with(command))
+        .collect::<Vec<_>>()[..]
+    {
+        [name] => CREDENTIAL_COMMANDS.contains(name),
+        _ => false,
+    }
+}
+
+/// A line with the arguments taken off if they might hold a password, so it's safe to keep.
+pub fn redact(line: &str) -> &str {
+    let line = line.trim();
+    match line.split_once(char::is_whitespace) {
+        Some((command, _)) if is_credential_command(command) => command,
+        _ => line,
+    }
+}
+
+/// Where the secret starts in a line being typed, if it has one, so it can be hidden. That's
+/// everything after the username for `login` and `register`, and everything after the command for
+/// `resume`.
+pub fn secret_start(line: &str) -> Option<usize> {
+    let command_start = line.len() - line.trim_start().len();
+    let command_end = command_start + line[command_start..].find(char::is_whitespace)?;
+    let command = &line[command_start..command_end];
+    if !is_credential_command(command) {
+        return None;
+    }
+    if "resume".starts_with(command) {
+        return after_separator(line, command_end);
+    }
+
+    let username_start = line.len() - line[command_end..].trim_start().len();
+    let username_end = username_start
+        + line[username_start..].find(|c: char| c.is_whitespace() || c == '|')?;
+    after_separator(line, username_end)
+}
+
+/// Where the next argument starts after the word ending at `end`, past a pipe if there is one.
+fn after_separator(line: &str, end: usize) -> Option<usize> {
+    let rest = &line[end..];
+    match rest.trim_start().strip_prefix('|') {
+        Some(after_pipe) => Some(line.len() - after_pipe.len()),
+        None => rest.chars().next().map(|space| end + space.len_utf8()),
+    }
+}
+
+/// The commands typed in past sessions, kept in a file one to a line.
+#[derive(Debug, Default)]
+pub struct History {
+    path: Option<PathBuf>,
+    pub lines: Vec<String>,
+}
+
+impl History {
+    /// Loads up to the last `max_lines` lines from `path`, trimming the file down to them. A
+    /// `max_lines` of 0 turns the history file off.
+    pub fn load(path: Option<PathBuf>, max_lines: usize) -> Self {
+        let Some(path) = path.filter(|_| max_lines > 0) else {
+            return Self::default();
+        };
+
+        let mut lines = fs::read_to_string(&path)
+            .unwrap_or_default()
+            .lines()
+            .map(|line| line.to_owned())
+            .collect::<Vec<_>>();
+        if lines.len() > max_lines {
+            lines.drain(..lines.len() - max_lines);
+            let _ = fs::write(
+                &path,
+                lines
+                    .iter()
+                    .map(|line| line.clone() + "\n")
+                    .collect::<String>(),
+            );
+        }
+
+        Self {
+            path: Some(path),
+            lines,
+        }
+    }
+
+    /// Remembers a line, redacted, for this session and the next.
+    pub fn push(&mut self, line: &str) {
+        let line = redact(line);
+        if line.is_empty() {
+            return;
+        }
+        self.lines.push(line.to_owned());
+
+        if let Some(path) = &self.path {
+            let _ = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .and_then(|mut file| writeln!(file, "{}", line));
+        }
+    }
+}
+
+/// A log of everything the server said in one session.
+#[derive(Debug)]
+pub struct Transcript(File);
+
+impl Transcript {
+    /// Starts a new log in `dir`, named for the time it was started. Logs started in the same
+    /// second get a number on the end rather than overwriting each other.
+    pub fn create(dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        let stamp = timestamp(SystemTime::now());
+        let mut number = 1;
+        loop {
+            let name = match number {
+                1 => format!("texla-{}.log", stamp),
+                n => format!("texla-{}-{}.log", stamp, n),
+            };
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(dir.join(name))
+            {
+                Ok(file) => return Ok(Self(file)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => number += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn write(&mut self, text: &str) {
+        let _ = writeln!(self.0, "{}", text);
+    }
+}
+
+/// A UTC time like `20241231-235959`.
+fn timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs) = (secs / 86400, secs % 86400);
+
+    // Howard Hinnant's days-to-civil algorithm, for days since 1970-01-01
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::history::{redact, secret_start, timestamp, History, Transcript};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("texla-history-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn credentials_are_redacted() {
+        assert_eq!(redact("login alice | hunter2"), "login");
+        assert_eq!(redact("  logi\talice|hunter2"), "logi");
+        assert_eq!(redact("register bob | pw"), "register");
+        assert_eq!(redact("resume 0123abcd"), "resume");
+        assert_eq!(redact("say login alice | pw"), "say login alice | pw");
+        assert_eq!(redact("look"), "look");
+    }
+
+    #[test]
+    fn only_what_the_server_would_take_as_credentials_is_redacted() {
+        assert_eq!(redact("l here"), "l here");
+        assert_eq!(redact("look here"), "look here");
+        assert_eq!(redact("r alice | pw"), "r alice | pw");
+        assert_eq!(redact("re alice | pw"), "re alice | pw");
+        assert_eq!(secret_start("l here"), None);
+        assert_eq!(secret_start("re 0123abcd"), None);
+        // Logout can't be used until login can't, so this can only be login
+        assert_eq!(redact("lo alice | pw"), "lo");
+        assert_eq!(redact("reg bob | pw"), "reg");
+    }
+
+    #[test]
+    fn secrets_start_after_the_pipe() {
+        assert_eq!(secret_start("login alice|pw"), Some(12));
+        assert_eq!(secret_start("reg bob | pw"), Some(9));
+        assert_eq!(secret_start("login alice"), None);
+        assert_eq!(secret_start("say a | b"), None);
+        assert_eq!(secret_start(""), None);
+    }
+
+    #[test]
+    fn secrets_without_pipes_are_hidden_too() {
+        assert_eq!(secret_start("login alice pw"), Some(12));
+        assert_eq!(secret_start("  login  alice  pw"), Some(15));
+        assert_eq!(redact("login alice pw"), "login");
+    }
+
+    #[test]
+    fn resume_tokens_are_hidden() {
+        assert_eq!(secret_start("resume 0123abcd"), Some(7));
+        assert_eq!(secret_start("res 0123abcd"), Some(4));
+        assert_eq!(secret_start("resume"), None);
+        assert_eq!(redact("res 0123abcd"), "res");
+    }
+
+    #[test]
+    fn history_keeps_the_last_lines_redacted() {
+        let path = temp_path("trim");
+        fs::write(&path, "one\ntwo\nthree\n").unwrap();
+
+        let mut history = History::load(Some(path.clone()), 2);
+        assert_eq!(history.lines, vec!["two", "three"]);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two\nthree\n");
+
+        history.push("login alice | hunter2");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two\nthree\nlogin\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn history_file_can_be_turned_off() {
+        let path = temp_path("off");
+
+        let mut history = History::load(Some(path.clone()), 0);
+        history.push("look");
+        assert_eq!(history.lines, vec!["look"]);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn transcripts_started_together_are_kept_apart() {
+        let dir = temp_path("logs");
+
+        let mut first = Transcript::create(&dir).unwrap();
+        let mut second = Transcript::create(&dir).unwrap();
+        first.write("first");
+        second.write("second");
+        drop((first, second));
+
+        let mut logs = fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| fs::read_to_string(entry.unwrap().path()).unwrap())
+            .collect::<Vec<_>>();
+        logs.sort();
+        assert_eq!(logs, vec!["first\n", "second\n"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn timestamps_are_utc() {
+        assert_eq!(timestamp(UNIX_EPOCH), "19700101-000000");
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(1_735_689_599)),
+            "20241231-235959"
+        );
+        assert_eq!(
+            timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)),
+            "20000229-000000"
+        );
+    }
+}
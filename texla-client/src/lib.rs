@@ -1,102 +1,243 @@
-use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use config::ClientConfig;
+use serde::Deserialize;
+use std::net::TcpStream;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
 use std::thread;
 use std::time::Duration;
-use tungstenite::{connect, Message};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{connect, Message, WebSocket};
 
-pub fn run(ev_in: Receiver<String>, ev_out: Sender<Output>) {
-    let mut socket = loop {
-        if let Ok((socket, _response)) = connect("ws://localhost:8080/socket") {
-            break socket;
+pub mod automation;
+pub mod config;
+pub mod editor;
+pub mod history;
+pub mod markup;
+
+pub fn run(config: &ClientConfig, ev_in: Receiver<String>, ev_out: Sender<Output>) {
+    let mut session = None;
+    let mut reconnecting = false;
+    loop {
+        let Some(mut socket) = connect_with_backoff(config, &ev_out) else {
+            ev_out
+                .send(Output::Error(format!(
+                    "Giving up on {} after {} attempts",
+                    config.url, config.max_attempts
+                )))
+                .unwrap();
+            return;
+        };
+        if reconnecting {
+            ev_out
+                .send(Output::Warning(format!("Reconnected to {}", config.url)))
+                .unwrap();
         }
-        thread::sleep(Duration::from_secs(1));
-    };
-    match socket.get_mut() {
-        tungstenite::stream::MaybeTlsStream::Plain(stream) => {
-            stream.set_nonblocking(true).unwrap();
+
+        // Ask for typed envelopes instead of plain text so errors can be told apart
+        let mut handshake = vec![Message::Text("protocol json".to_owned())];
+        // Tokens are single use, the server sends a new one once the session is resumed
+        if let Some(token) = session.take() {
+            handshake.push(Message::Text(format!("resume {}", token)));
+        }
+        let closed = match handshake
+            .into_iter()
+            .try_for_each(|msg| send(&mut socket, msg))
+        {
+            Ok(()) => serve(&mut socket, &ev_in, &ev_out, &mut session),
+            Err(e) => Closed::Lost(e),
+        };
+
+        match closed {
+            Closed::ByUser => return,
+            Closed::ByServer(reason) => {
+                ev_out
+                    .send(Output::Error(format!("Disconnected: {}", reason)))
+                    .unwrap();
+                return;
+            }
+            Closed::Lost(reason) => {
+                ev_out
+                    .send(Output::Warning(format!(
+                        "Connection lost ({}), reconnecting...",
+                        reason
+                    )))
+                    .unwrap();
+                reconnecting = true;
+            }
         }
-        _ => ev_out
-            .send(Output::Warning(
-                "Can't set nonblocking mode for TLS stream".to_owned(),
-            ))
-            .unwrap(),
     }
+}
+
+enum Closed {
+    /// The user is done, so there's nothing to reconnect for.
+    ByUser,
+    /// The server hung up on purpose, so reconnecting would just get us kicked again.
+    ByServer(String),
+    Lost(String),
+}
 
-    let (tx, rx) = mpsc::channel();
-    let (other_tx, other_rx) = mpsc::channel();
+/// Passes messages between the user and the server until one of them hangs up.
+fn serve(
+    socket: &mut WebSocket<MaybeTlsStream<TcpStream>>,
+    ev_in: &Receiver<String>,
+    ev_out: &Sender<Output>,
+    session: &mut Option<String>,
+) -> Closed {
+    use tungstenite::protocol::frame::coding::CloseCode;
+    use tungstenite::Error;
 
-    thread::spawn(move || loop {
+    loop {
         loop {
-            match rx.try_recv() {
-                Ok(msg @ Message::Text(_)) => {
-                    socket.send(msg).expect("Can't send message");
-                }
-                Ok(Message::Close(_)) | Err(TryRecvError::Disconnected) => {
-                    socket.close(None).expect("Can't close");
-                }
+            match ev_in.try_recv() {
                 Ok(msg) => {
-                    ev_out
-                        .send(Output::Warning(format!(
-                            "Sent unsupported message type: {msg}"
-                        )))
-                        .unwrap();
+                    if let Err(e) = send(socket, Message::Text(msg)) {
+                        return Closed::Lost(e);
+                    }
                 }
                 Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    let _ = socket.close(None);
+                    let _ = socket.flush();
+                    return Closed::ByUser;
+                }
             }
         }
+        // Finish writing anything a previous send couldn't get out in one go
+        let _ = socket.flush();
 
         loop {
-            use tungstenite::{error::ProtocolError, Error};
-
-            match socket.read() {
-                Ok(Message::Text(msg)) => {
-                    ev_out.send(Output::Text(msg)).unwrap();
-                }
-                Ok(msg) => {
-                    ev_out
-                        .send(Output::Warning(format!(
-                            "Received unsupported message type: {msg}"
-                        )))
-                        .unwrap();
-                }
-                Err(Error::ConnectionClosed)
-                | Err(Error::AlreadyClosed)
-                | Err(Error::Protocol(ProtocolError::ResetWithoutClosingHandshake)) => {
-                    println!("Connection closed");
-                    other_tx
-                        .send(OtherMessage::Close)
-                        .expect("Can't send close message");
-                    return;
-                }
-                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                    break;
-                }
-                Err(e) => {
-                    ev_out.send(Output::Warning(format!("Error: {e}"))).unwrap();
-                    socket.close(None).expect("Can't close");
+            let output = match socket.read() {
+                Ok(Message::Text(msg)) => match Incoming::parse(msg) {
+                    Incoming::Show(output) => output,
+                    Incoming::Session(token) => {
+                        *session = Some(token);
+                        continue;
+                    }
+                    Incoming::Disconnect(reason) => return Closed::ByServer(reason),
+                    Incoming::Ignore => continue,
+                },
+                Ok(Message::Close(Some(frame))) if frame.code == CloseCode::Policy => {
+                    return Closed::ByServer(frame.reason.into_owned());
                 }
+                Ok(Message::Ping(_) | Message::Pong(_) | Message::Close(_)) => continue,
+                Ok(msg) => Output::Warning(format!("Received unsupported message type: {msg}")),
+                Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Closed::Lost(e.to_string()),
+            };
+            if ev_out.send(output).is_err() {
+                return Closed::ByUser;
             }
         }
-    });
 
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// Tries to connect until it works or `config.max_attempts` runs out, warning about each failure.
+fn connect_with_backoff(
+    config: &ClientConfig,
+    ev_out: &Sender<Output>,
+) -> Option<WebSocket<MaybeTlsStream<TcpStream>>> {
+    let mut attempt = 0;
     loop {
-        match other_rx.try_recv() {
-            Ok(OtherMessage::Close) | Err(TryRecvError::Disconnected) => {
-                return;
-            }
-            Err(TryRecvError::Empty) => {}
-        }
+        attempt += 1;
+        let err = match connect(config.url.as_str()) {
+            Ok((socket, _response)) => match set_nonblocking(&socket) {
+                Ok(()) => return Some(socket),
+                Err(e) => e.to_string(),
+            },
+            Err(e) => e.to_string(),
+        };
 
-        while let Ok(msg) = ev_in.try_recv() {
-            tx.send(Message::Text(msg)).expect("Can't send message");
+        if config.max_attempts != 0 && attempt >= config.max_attempts {
+            ev_out
+                .send(Output::Warning(format!(
+                    "Can't connect to {}: {}",
+                    config.url, err
+                )))
+                .unwrap();
+            return None;
         }
+
+        let delay = config.backoff(attempt);
+        ev_out
+            .send(Output::Warning(format!(
+                "Can't connect to {}: {} (attempt {}{}, retrying in {:.1}s)",
+                config.url,
+                err,
+                attempt,
+                match config.max_attempts {
+                    0 => String::new(),
+                    max => format!(" of {}", max),
+                },
+                delay.as_secs_f32()
+            )))
+            .unwrap();
+        thread::sleep(delay);
+    }
+}
+
+/// Sends a message, treating a full socket buffer as success since the message stays queued until
+/// the next flush.
+fn send(socket: &mut WebSocket<MaybeTlsStream<TcpStream>>, msg: Message) -> Result<(), String> {
+    match socket.send(msg) {
+        Ok(()) => Ok(()),
+        Err(tungstenite::Error::Io(e)) if e.kind() == std::io::ErrorKind::WouldBlock => Ok(()),
+        Err(e) => Err(e.to_string()),
     }
 }
 
-enum OtherMessage {
-    Close,
+/// Makes reads return immediately, so the socket can be polled from the same thread that writes
+/// to it. TLS streams are set up already by the time this is called, so it's safe to change the
+/// underlying TCP stream out from under them.
+fn set_nonblocking(socket: &WebSocket<MaybeTlsStream<TcpStream>>) -> std::io::Result<()> {
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.set_nonblocking(true),
+        MaybeTlsStream::Rustls(stream) => stream.get_ref().set_nonblocking(true),
+        _ => Err(std::io::Error::other("unsupported stream type")),
+    }
 }
 
 pub enum Output {
     Text(String),
     Warning(String),
+    Error(String),
+}
+
+/// A message from a server speaking the structured protocol.
+#[derive(Deserialize, Debug)]
+struct Envelope {
+    kind: String,
+    ok: bool,
+    body: String,
+}
+
+/// What to do with a message from the server.
+enum Incoming {
+    Show(Output),
+    /// A token to resume the session with if the connection drops.
+    Session(String),
+    /// The server is about to hang up on us, and why.
+    Disconnect(String),
+    /// Protocol chatter that shouldn't be shown.
+    Ignore,
+}
+
+impl Incoming {
+    /// Servers that don't understand the handshake send plain text, which is shown as is.
+    fn parse(msg: String) -> Self {
+        match serde_json::from_str::<Envelope>(&msg) {
+            Ok(Envelope { kind, .. }) if kind == "protocol" => Incoming::Ignore,
+            Ok(Envelope {
+                kind,
+                ok: true,
+                body,
+            }) if kind == "session" => Incoming::Session(body),
+            Ok(Envelope { kind, body, .. }) if kind == "disconnect" => Incoming::Disconnect(body),
+            Ok(Envelope { ok: true, body, .. }) => Incoming::Show(Output::Text(body)),
+            Ok(Envelope {
+                ok: false, body, ..
+            }) => Incoming::Show(Output::Error(body)),
+            Err(_) => Incoming::Show(Output::Text(msg)),
+        }
+    }
 }
@@ -1,33 +1,83 @@
 use std::io::{stdout, Write};
-use std::sync::mpsc::TryRecvError;
+use std::path::PathBuf;
+use std::sync::mpsc::{Sender, TryRecvError};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crossterm::cursor::MoveTo;
-use crossterm::event::{poll, read, Event, KeyCode};
+use crossterm::event::{
+    poll, read, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyModifiers,
+    MouseEventKind,
+};
 use crossterm::execute;
-use crossterm::style::Stylize;
+use crossterm::style::{Color, ContentStyle, Stylize};
 use crossterm::terminal::EnterAlternateScreen;
-use itertools::Itertools;
+use texla_client::automation::Automation;
+use texla_client::config::{ClientConfig, ConfigError, USAGE};
+use texla_client::editor::LineEditor;
+use texla_client::history::{redact, secret_start, History, Transcript};
+use texla_client::markup::{self, escape, Line, Span};
 use texla_client::{run, Output};
-use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
 fn main() {
-    Client::default().run();
+    let config = match ClientConfig::load(std::env::args().skip(1), |key| std::env::var(key).ok()) {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    Client {
+        scrollback: config.scrollback_lines.max(1),
+        color: config.color,
+        ..Default::default()
+    }
+    .run(config);
 }
 
+/// How many lines a turn of the mouse wheel scrolls.
+const WHEEL_LINES: isize = 3;
+
 #[derive(Debug, Default)]
 struct Client {
-    input: String,
-    cursor: usize,
-    output_history: Vec<String>,
-    input_history: Vec<String>,
+    input: LineEditor,
+    output_history: Vec<Line>,
+    /// How many lines of output to keep.
+    scrollback: usize,
+    /// How many lines up from the newest output the view is scrolled.
+    scroll: usize,
+    /// Whether to show the server's colors and styles, or just the text.
+    color: bool,
+    input_history: History,
+    /// Where the session's being logged, if it is.
+    transcript: Option<Transcript>,
+    automation: Automation,
+    automation_file: PathBuf,
     input_history_index: Option<usize>,
 }
 
 impl Client {
-    fn run(&mut self) {
-        execute!(stdout(), EnterAlternateScreen).unwrap();
+    fn run(&mut self, config: ClientConfig) {
+        self.input_history = History::load(config.history_file.clone(), config.history_lines);
+        if let Some(dir) = &config.log_dir {
+            match Transcript::create(dir) {
+                Ok(transcript) => self.transcript = Some(transcript),
+                Err(err) => self.push_output(&format!(
+                    "{{yellow}}{}",
+                    escape(&format!("Can't start a log in {}: {}", dir.display(), err))
+                )),
+            }
+        }
+
+        self.automation_file = config.automation_file.clone();
+        self.reload_automation(false);
+
+        execute!(stdout(), EnterAlternateScreen, EnableMouseCapture).unwrap();
         crossterm::terminal::enable_raw_mode().unwrap();
 
         let (ev_in_tx, ev_in_rx) = std::sync::mpsc::channel();
@@ -35,23 +85,46 @@ impl Client {
         let (stopped_tx, stopped_rx) = std::sync::mpsc::channel();
 
         thread::spawn(move || {
-            run(ev_in_rx, ev_out_tx);
+            run(&config, ev_in_rx, ev_out_tx);
             stopped_tx.send(()).unwrap();
         });
 
         self.draw_borders();
 
+        let mut stopped = false;
         'main: loop {
             loop {
                 match ev_out_rx.try_recv() {
                     Ok(msg) => match msg {
                         Output::Text(msg) => {
-                            self.output_history
-                                .extend(msg.split("\n").map(|s| s.to_owned()));
+                            self.log(&markup::strip(&msg));
+                            let now = Instant::now();
+                            let mut responses = Vec::new();
+                            let mut dropped = 0;
+                            let lines = markup::parse(&msg)
+                                .into_iter()
+                                .filter_map(|line| {
+                                    let triggered = self.automation.trigger(line, now);
+                                    responses.extend(triggered.sends);
+                                    dropped += triggered.dropped;
+                                    triggered.line
+                                })
+                                .collect();
+                            self.push_lines(lines);
+                            for response in responses {
+                                self.submit(&response, false, &ev_in_tx);
+                            }
+                            if dropped > 0 {
+                                self.push_output(&format!(
+                                    "{{yellow}}Triggers are sending too fast, held back {}.",
+                                    dropped
+                                ));
+                            }
                         }
-                        Output::Warning(msg) => {
-                            self.output_history
-                                .extend(msg.split("\n").map(|s| s.yellow().to_string()));
+                        Output::Warning(msg) => self.warn(&msg),
+                        Output::Error(msg) => {
+                            self.log(&markup::strip(&msg));
+                            self.push_output(&format!("{{red}}{}", escape(&msg)));
                         }
                     },
                     Err(TryRecvError::Empty) => break,
@@ -62,6 +135,7 @@ impl Client {
             }
 
             if stopped_rx.try_recv().is_ok() {
+                stopped = true;
                 break 'main;
             }
 
@@ -70,60 +144,83 @@ impl Client {
                 match read().unwrap() {
                     Event::Key(event) => match event.code {
                         KeyCode::Char('\n') | KeyCode::Enter => {
-                            self.input = self.input.trim().to_owned();
-                            self.input_history.push(self.input.clone());
-                            self.output_history
-                                .push(format!("> {}", self.input.clone().dark_yellow()));
-                            ev_in_tx
-                                .send(self.input.clone())
-                                .expect("Can't send message");
-                            self.input.clear();
-                            self.cursor = 0;
+                            let input = self.input.take().trim().to_owned();
                             self.input_history_index = None;
+                            self.scroll = 0;
+                            self.submit(&input, true, &ev_in_tx);
+                        }
+                        KeyCode::F(number) => {
+                            if let Some(command) = self.automation.macro_for(number) {
+                                let command = command.to_owned();
+                                self.scroll = 0;
+                                self.submit(&command, false, &ev_in_tx);
+                            }
+                        }
+                        KeyCode::Char('w') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.input.kill_word();
                         }
+                        KeyCode::Char('u') if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.input.kill_to_start();
+                        }
+                        KeyCode::Char(_) if event.modifiers.contains(KeyModifiers::CONTROL) => {}
                         KeyCode::Char(c) => {
-                            self.input.insert(self.cursor, c);
-                            self.cursor += 1;
+                            self.input.insert(c);
                         }
                         KeyCode::Backspace => {
-                            self.input.pop();
-                            if self.cursor > 0 {
-                                self.cursor -= 1;
-                            }
+                            self.input.backspace();
+                        }
+                        KeyCode::Delete => {
+                            self.input.delete();
                         }
                         KeyCode::Esc => {
                             break 'main;
                         }
+                        KeyCode::PageUp => {
+                            self.scroll_by(Self::page_lines());
+                        }
+                        KeyCode::PageDown => {
+                            self.scroll_by(-Self::page_lines());
+                        }
+                        KeyCode::End if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.scroll = 0;
+                        }
+                        KeyCode::End => {
+                            self.input.end();
+                        }
+                        KeyCode::Home => {
+                            self.input.home();
+                        }
+                        KeyCode::Left if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.input.word_left();
+                        }
+                        KeyCode::Right if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                            self.input.word_right();
+                        }
                         KeyCode::Left => {
-                            if self.cursor > 0 {
-                                self.cursor -= 1;
-                            }
+                            self.input.left();
                         }
                         KeyCode::Right => {
-                            if self.cursor < self.input.graphemes(true).count() {
-                                self.cursor += 1;
-                            }
+                            self.input.right();
                         }
+                        KeyCode::Up if self.input_history.lines.is_empty() => {}
                         KeyCode::Up => {
                             if let Some(index) = self.input_history_index {
                                 if index > 0 {
                                     self.input_history_index = Some(index - 1);
                                 }
                             } else {
-                                self.input_history_index = Some(self.input_history.len() - 1);
+                                self.input_history_index = Some(self.input_history.lines.len() - 1);
                             }
 
                             if let Some(index) = self.input_history_index {
-                                self.input = self.input_history[index].clone();
-                                self.cursor = self.input.graphemes(true).count();
+                                self.input.set(self.input_history.lines[index].clone());
                             } else {
-                                self.input.clear();
-                                self.cursor = 0;
+                                self.input.take();
                             }
                         }
                         KeyCode::Down => {
                             if let Some(index) = self.input_history_index {
-                                if index < self.input_history.len() - 1 {
+                                if index < self.input_history.lines.len() - 1 {
                                     self.input_history_index = Some(index + 1);
                                 } else {
                                     self.input_history_index = None;
@@ -131,15 +228,18 @@ impl Client {
                             }
 
                             if let Some(index) = self.input_history_index {
-                                self.input = self.input_history[index].clone();
-                                self.cursor = self.input.graphemes(true).count();
+                                self.input.set(self.input_history.lines[index].clone());
                             } else {
-                                self.input.clear();
-                                self.cursor = 0;
+                                self.input.take();
                             }
                         }
                         _ => {}
                     },
+                    Event::Mouse(event) => match event.kind {
+                        MouseEventKind::ScrollUp => self.scroll_by(WHEEL_LINES),
+                        MouseEventKind::ScrollDown => self.scroll_by(-WHEEL_LINES),
+                        _ => {}
+                    },
                     Event::Resize(_, _) => {
                         resized = true;
                     }
@@ -154,7 +254,112 @@ impl Client {
         }
 
         crossterm::terminal::disable_raw_mode().unwrap();
-        execute!(stdout(), crossterm::terminal::LeaveAlternateScreen).unwrap();
+        execute!(
+            stdout(),
+            DisableMouseCapture,
+            crossterm::terminal::LeaveAlternateScreen
+        )
+        .unwrap();
+
+        // Leave the reason the connection ended where it can still be read
+        if stopped {
+            if let Some(line) = self.output_history.last() {
+                println!("{}", markup::render(line));
+            }
+        }
+    }
+
+    /// Adds a message in markup to the end of the output, keeping the view where it is if the
+    /// player has scrolled up, and forgetting the oldest lines past the scrollback limit.
+    fn push_output(&mut self, message: &str) {
+        self.push_lines(markup::parse(message));
+    }
+
+    fn push_lines(&mut self, lines: Vec<Line>) {
+        let before = self.output_history.len();
+        if self.color {
+            self.output_history.extend(lines);
+        } else {
+            self.output_history.extend(lines.iter().map(markup::plain));
+        }
+        if self.scroll > 0 {
+            self.scroll += self.output_history.len() - before;
+        }
+
+        let excess = self.output_history.len().saturating_sub(self.scrollback);
+        self.output_history.drain(..excess);
+        self.scroll_by(0);
+    }
+
+    /// Echoes a line and sends it to the server, aliases expanded, or runs it here if it's a client
+    /// command like `/reload`. Lines the user `typed` are kept in the input history.
+    fn submit(&mut self, line: &str, typed: bool, ev_in_tx: &Sender<String>) {
+        let expanded = self.automation.expand(line);
+        // An alias can stand for a command with a password in it
+        let shown = if redact(&expanded) == expanded.trim() {
+            line
+        } else {
+            redact(&expanded)
+        };
+        self.push_output(&format!("> {{dark_yellow}}{}", escape(shown)));
+        if typed {
+            self.input_history.push(shown);
+        }
+
+        match line.strip_prefix('/') {
+            Some("reload") => self.reload_automation(true),
+            Some(command) => self.push_output(&format!(
+                "{{red}}{}",
+                escape(&format!(
+                    "Unknown client command /{}. The only one is /reload.",
+                    command
+                ))
+            )),
+            None => {
+                // The connection can have given up for good while output is still being shown
+                if ev_in_tx.send(expanded).is_err() {
+                    self.warn("Not connected.");
+                }
+            }
+        }
+    }
+
+    fn warn(&mut self, msg: &str) {
+        self.log(msg);
+        self.push_output(&format!("{{yellow}}{}", escape(msg)));
+    }
+
+    /// Reads the aliases, triggers and macros again, keeping the old ones if the file's broken.
+    fn reload_automation(&mut self, report: bool) {
+        match Automation::load(&self.automation_file) {
+            Ok(automation) => {
+                self.automation = automation;
+                if report {
+                    self.push_output(&format!("Loaded {}.", self.automation.summary()));
+                }
+            }
+            Err(err) => self.push_output(&format!("{{red}}{}", escape(&err))),
+        }
+    }
+
+    fn log(&mut self, text: &str) {
+        if let Some(transcript) = &mut self.transcript {
+            transcript.write(text);
+        }
+    }
+
+    /// Scrolls up by `lines`, or down if it's negative, without going past either end.
+    fn scroll_by(&mut self, lines: isize) {
+        self.scroll = self
+            .scroll
+            .saturating_add_signed(lines)
+            .min(self.output_history.len().saturating_sub(1));
+    }
+
+    /// How far PageUp and PageDown scroll: a screenful, less a line to keep your place by.
+    fn page_lines() -> isize {
+        let (_, height) = crossterm::terminal::size().unwrap();
+        (height as isize - 5).max(1)
     }
 
     fn draw_borders(&self) {
@@ -202,84 +407,78 @@ impl Client {
         let output_width = width - 4;
         let output_height = height - 4;
 
+        let (input_text, cursor_column) = self.input.display(secret_start(self.input.text()));
         let input = Self::pad_line(
-            format!("> {}", self.input.clone().dark_yellow()),
-            width as usize,
+            markup::render(&vec![
+                Span {
+                    style: ContentStyle::default(),
+                    text: "> ".to_owned(),
+                },
+                Span {
+                    style: ContentStyle::default().with(Color::DarkYellow),
+                    text: input_text.clone(),
+                },
+            ]),
+            2 + input_text.width(),
+            output_width as usize,
         );
         let output = self
             .output_history
             .iter()
             .rev()
+            .skip(self.scroll)
             .take(output_height as usize)
-            .flat_map(|msg| {
-                Self::wrap_lines(msg.clone(), output_width as usize)
+            .flat_map(|line| {
+                Self::wrap_line(line, output_width as usize)
                     .into_iter()
                     .rev()
             })
             .take(output_height as usize)
             .collect::<Vec<_>>();
 
-        for (y, line) in output.into_iter().enumerate() {
+        // Blank out whatever's left over from before scrolling to the top
+        let blank = Self::pad_line(String::new(), 0, output_width as usize);
+        for y in 0..output_height as usize {
             execute!(stdout, MoveTo(2, height - 4 - y as u16)).unwrap();
-            write!(stdout, "{}", line).unwrap();
+            write!(stdout, "{}", output.get(y).unwrap_or(&blank)).unwrap();
+        }
+
+        execute!(stdout, MoveTo(1, height - 3)).unwrap();
+        let separator_width = width as usize - 2;
+        let more = if self.scroll > 0 {
+            format!(" {} more below, Ctrl+End to jump down ", self.scroll)
+                .chars()
+                .take(separator_width.saturating_sub(1))
+                .collect::<String>()
+        } else {
+            String::new()
+        };
+        let rest = "─".repeat(separator_width - more.chars().count().min(separator_width));
+        if more.is_empty() {
+            write!(stdout, "{}", rest).unwrap();
+        } else {
+            write!(stdout, "─{}{}", more.reverse(), &rest["─".len()..]).unwrap();
         }
 
         execute!(stdout, MoveTo(2, height - 2)).unwrap();
         write!(stdout, "{}", input).unwrap();
 
-        execute!(stdout, MoveTo(4 + self.cursor as u16, height - 2)).unwrap();
+        execute!(stdout, MoveTo(4 + cursor_column as u16, height - 2)).unwrap();
 
         stdout.flush().unwrap();
     }
 
-    fn wrap_lines(msg: String, width: usize) -> Vec<String> {
-        msg.split("\n")
-            .flat_map(|part| {
-                let mut part = part.to_owned();
-                let mut parts = Vec::new();
-                while part.graphemes(true).count() > width {
-                    let mut index = width;
-                    while index > 0
-                        && !char::is_whitespace(
-                            part.graphemes(true)
-                                .nth(index)
-                                .unwrap()
-                                .chars()
-                                .next()
-                                .unwrap(),
-                        )
-                    {
-                        index -= 1;
-                    }
-                    if index == 0 {
-                        index = width;
-                    }
-                    parts.push(
-                        part.graphemes(true)
-                            .take(index)
-                            .join("")
-                            .trim_end()
-                            .to_owned(),
-                    );
-                    part = part
-                        .graphemes(true)
-                        .skip(index)
-                        .join("")
-                        .trim_start()
-                        .to_owned();
-                }
-                if !part.is_empty() {
-                    parts.push(part.to_owned());
-                }
-                parts.into_iter().map(|part| Self::pad_line(part, width))
-            })
-            .collect::<Vec<_>>()
+    /// Wraps a line to `width` columns, writing the rows out styled and padded to the full width.
+    fn wrap_line(line: &Line, width: usize) -> Vec<String> {
+        markup::wrap(line, width)
+            .iter()
+            .map(|row| Self::pad_line(markup::render(row), markup::width(row), width))
+            .collect()
     }
 
-    fn pad_line(mut msg: String, width: usize) -> String {
-        while msg.graphemes(true).count() < width {
-            msg.push(' ');
-        }
+    /// Pads text that takes up `columns` columns out to `width`.
+    fn pad_line(mut msg: String, columns: usize, width: usize) -> String {
+        msg.push_str(&" ".repeat(width.saturating_sub(columns)));
         msg
     }
 }
@@ -0,0 +1,298 @@
+//! The server's styling tags, like `{bold}The Void{reset}`. Anything in braces that isn't a tag is
+//! shown as typed, and `{{` is a literal `{`.
+
+use crossterm::style::{Attribute, Color, ContentStyle};
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+/// Some text and how it looks.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Span {
+    pub style: ContentStyle,
+    pub text: String,
+}
+
+/// A line of output, ready to be drawn.
+pub type Line = Vec<Span>;
+
+/// Splits a message into lines of styled text. Styles carry on from one line to the next.
+pub fn parse(message: &str) -> Vec<Line> {
+    let mut lines = vec![Line::new()];
+    let mut style = ContentStyle::default();
+    let mut text = String::new();
+    let mut rest = message;
+
+    let flush = |lines: &mut Vec<Line>, style, text: &mut String| {
+        if !text.is_empty() {
+            lines.last_mut().unwrap().push(Span {
+                style,
+                text: std::mem::take(text),
+            });
+        }
+    };
+
+    while let Some(start) = rest.find(['{', '\n']) {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix('\n') {
+            flush(&mut lines, style, &mut text);
+            lines.push(Line::new());
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("{{") {
+            text.push('{');
+            rest = after;
+            continue;
+        }
+        match rest
+            .find('}')
+            .and_then(|end| apply(style, &rest[1..end]).map(|style| (end, style)))
+        {
+            Some((end, new_style)) => {
+                flush(&mut lines, style, &mut text);
+                style = new_style;
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    flush(&mut lines, style, &mut text);
+
+    lines
+}
+
+/// Makes `text` show as typed, even if it has braces in it.
+pub fn escape(text: &str) -> String {
+    text.replace('{', "{{")
+}
+
+/// A message without its tags, for writing somewhere that can't show them.
+pub fn strip(message: &str) -> String {
+    parse(message)
+        .iter()
+        .map(|line| {
+            line.iter()
+                .map(|span| span.text.as_str())
+                .collect::<String>()
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A line without its styling, for terminals that can't show it.
+pub fn plain(line: &Line) -> Line {
+    line.iter()
+        .map(|span| Span {
+            style: ContentStyle::default(),
+            text: span.text.clone(),
+        })
+        .collect()
+}
+
+/// Writes styled text out with the escape codes the terminal needs to show it.
+pub fn render(line: &Line) -> String {
+    line.iter()
+        .map(|span| span.style.apply(&span.text).to_string())
+        .collect()
+}
+
+/// How many terminal columns a line takes up.
+pub fn width(line: &Line) -> usize {
+    line.iter().map(|span| span.text.width()).sum()
+}
+
+/// Splits a line into rows of at most `width` columns, breaking at spaces where it can.
+pub fn wrap(line: &Line, width: usize) -> Vec<Line> {
+    let is_space = |g: &str| g.chars().all(char::is_whitespace);
+    let graphemes = line
+        .iter()
+        .flat_map(|span| span.text.graphemes(true).map(|g| (span.style, g)))
+        .collect::<Vec<_>>();
+
+    let mut rows = Vec::new();
+    let mut rest = &graphemes[..];
+    loop {
+        let mut columns = 0;
+        let fits = rest
+            .iter()
+            .take_while(|(_, g)| {
+                columns += g.width();
+                columns <= width
+            })
+            .count();
+        if fits == rest.len() {
+            break;
+        }
+
+        let index = rest[..=fits]
+            .iter()
+            .rposition(|(_, g)| is_space(g))
+            .filter(|index| *index > 0)
+            .unwrap_or(fits.max(1));
+        let mut row = &rest[..index];
+        while let [start @ .., (_, g)] = row {
+            if !is_space(g) {
+                break;
+            }
+            row = start;
+        }
+        rows.push(row);
+        rest = &rest[index..];
+        while let [(_, g), end @ ..] = rest {
+            if !is_space(g) {
+                break;
+            }
+            rest = end;
+        }
+    }
+    if !rest.is_empty() {
+        rows.push(rest);
+    }
+
+    rows.into_iter()
+        .map(|row| {
+            let mut line = Line::new();
+            for (style, g) in row {
+                match line.last_mut() {
+                    Some(span) if span.style == *style => span.text.push_str(g),
+                    _ => line.push(Span {
+                        style: *style,
+                        text: g.to_string(),
+                    }),
+                }
+            }
+            line
+        })
+        .collect()
+}
+
+pub fn is_tag(tag: &str) -> bool {
+    apply(ContentStyle::default(), tag).is_some()
+}
+
+/// The style after a tag, or nothing if it isn't one.
+pub fn apply(mut style: ContentStyle, tag: &str) -> Option<ContentStyle> {
+    match tag {
+        "reset" => return Some(ContentStyle::default()),
+        "bold" => style.attributes.set(Attribute::Bold),
+        "italic" => style.attributes.set(Attribute::Italic),
+        "underline" => style.attributes.set(Attribute::Underlined),
+        _ => {
+            style.foreground_color = Some(match tag {
+                "red" => Color::Red,
+                "green" => Color::Green,
+                "yellow" => Color::Yellow,
+                "blue" => Color::Blue,
+                "magenta" => Color::Magenta,
+                "cyan" => Color::Cyan,
+                "white" => Color::White,
+                "gray" => Color::Grey,
+                "dark_yellow" => Color::DarkYellow,
+                _ => return None,
+            })
+        }
+    }
+    Some(style)
+}
+
+#[cfg(test)]
+mod tests {
+    use crossterm::style::{Attribute, Color, ContentStyle};
+
+    use crate::markup::{escape, parse, plain, strip, wrap, Line, Span};
+
+    fn span(style: ContentStyle, text: &str) -> Span {
+        Span {
+            style,
+            text: text.to_owned(),
+        }
+    }
+
+    fn colored(color: Color) -> ContentStyle {
+        ContentStyle {
+            foreground_color: Some(color),
+            ..Default::default()
+        }
+    }
+
+    fn texts(line: &Line) -> Vec<&str> {
+        line.iter().map(|span| span.text.as_str()).collect()
+    }
+
+    #[test]
+    fn tags_style_what_comes_after() {
+        let mut bold_red = colored(Color::Red);
+        bold_red.attributes.set(Attribute::Bold);
+
+        assert_eq!(
+            parse("a{red}b{bold}c{reset}d"),
+            vec![vec![
+                span(ContentStyle::default(), "a"),
+                span(colored(Color::Red), "b"),
+                span(bold_red, "c"),
+                span(ContentStyle::default(), "d"),
+            ]]
+        );
+    }
+
+    #[test]
+    fn other_braces_are_kept() {
+        assert_eq!(
+            parse("{{red} {smiles} {bold"),
+            vec![vec![span(ContentStyle::default(), "{red} {smiles} {bold")]]
+        );
+        assert_eq!(strip("{{red}{cyan}x{reset}}"), "{red}x}");
+    }
+
+    #[test]
+    fn escaped_text_is_shown_as_typed() {
+        for text in ["{red}", "{{bold}", "a { b }"] {
+            assert_eq!(strip(&escape(text)), text);
+        }
+    }
+
+    #[test]
+    fn styles_carry_over_lines() {
+        assert_eq!(
+            parse("{green}one\ntwo{reset}\nthree"),
+            vec![
+                vec![span(colored(Color::Green), "one")],
+                vec![span(colored(Color::Green), "two")],
+                vec![span(ContentStyle::default(), "three")],
+            ]
+        );
+        assert_eq!(
+            plain(&parse("{green}one").remove(0)),
+            vec![span(ContentStyle::default(), "one")]
+        );
+    }
+
+    #[test]
+    fn styled_text_wraps_at_spaces() {
+        let line = parse("the {cyan}quick brown{reset} fox").remove(0);
+        let rows = wrap(&line, 11);
+
+        assert_eq!(
+            rows.iter().map(texts).collect::<Vec<_>>(),
+            vec![vec!["the ", "quick"], vec!["brown", " fox"]]
+        );
+        assert_eq!(rows[0][1].style, colored(Color::Cyan));
+        assert_eq!(rows[1][0].style, colored(Color::Cyan));
+    }
+
+    #[test]
+    fn wide_text_wraps_by_columns() {
+        let line = parse("日本語です").remove(0);
+
+        assert_eq!(
+            wrap(&line, 4).iter().map(texts).collect::<Vec<_>>(),
+            vec![vec!["日本"], vec!["語で"], vec!["す"]]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! How long a tick takes with thousands of commands to dispatch. Run with `cargo bench`.
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+use texla_server::config::ServerConfig;
+use texla_server::{
+    headless_app, Connection, Object, Player, PlayerCommand, PlayerConnection, SpawnRoom,
+};
+
+const PLAYERS: usize = 100;
+const COMMANDS_PER_TICK: usize = 5000;
+const TICKS: u32 = 50;
+
+/// A mix of exact names, aliases, abbreviations and typos.
+const LINES: &[&str] = &[
+    "look",
+    "l",
+    "loo",
+    "echo hi | there",
+    "help echo",
+    "mail",
+    "xyzzy",
+];
+
+fn main() {
+    let mut app = headless_app();
+    app.add_plugins(MinimalPlugins)
+        .insert_resource(ServerConfig::default());
+
+    let world = app.world_mut();
+    let room = world.spawn(Object::default()).id();
+    world.insert_resource(SpawnRoom(room));
+    let conns = (0..PLAYERS)
+        .map(|i| {
+            let object = world
+                .spawn((
+                    Object::default(),
+                    Player {
+                        username: format!("player{}", i),
+                        password_hash: String::new(),
+                    },
+                ))
+                .set_parent(room)
+                .id();
+            world.spawn((Connection, PlayerConnection { object })).id()
+        })
+        .collect::<Vec<_>>();
+    app.update();
+
+    let mut total = Duration::ZERO;
+    for _ in 0..TICKS {
+        let world = app.world_mut();
+        for i in 0..COMMANDS_PER_TICK {
+            let line = LINES[i % LINES.len()];
+            let conn = conns[i % conns.len()];
+            world.spawn(PlayerCommand::parse(line, conn));
+        }
+
+        let start = Instant::now();
+        app.update();
+        total += start.elapsed();
+    }
+
+    let per_tick = total / TICKS;
+    println!(
+        "{} commands per tick: {:?} per tick, {:.0} commands per second",
+        COMMANDS_PER_TICK,
+        per_tick,
+        COMMANDS_PER_TICK as f64 / per_tick.as_secs_f64()
+    );
+}
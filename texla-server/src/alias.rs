@@ -0,0 +1,253 @@
+use std::collections::BTreeMap;
+
+use bevy::prelude::*;
+
+use crate::markup::escape;
+use crate::prelude::*;
+use crate::{route_commands, CommandInfo, CommandInner, PREFIX_COMMANDS};
+
+pub struct AliasPlugin;
+
+impl Plugin for AliasPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                expand_aliases
+                    .in_set(ResolveCommandsSet)
+                    .before(route_commands),
+                (
+                    preprocess_commands::<AliasCommand>,
+                    preprocess_commands::<UnaliasCommand>,
+                )
+                    .in_set(PreprocessCommandsSet),
+                (handle_alias, handle_unalias).in_set(HandleCommandsSet),
+            ),
+        );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        CommandHandler::<AliasCommand>::new("alias")
+            .with_args([
+                Arg::new("name", ArgKind::Text).optional(),
+                Arg::new("command", ArgKind::Text).optional(),
+            ])
+            .with_summary("Lists your aliases, or makes a new one.")
+            .with_help(
+                "After `alias gn | go north`, typing `gn` goes north. Anything typed after an \
+                alias is added to the end of its command.",
+            ),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<UnaliasCommand>::new("unalias")
+            .with_args([Arg::new("name", ArgKind::Text)])
+            .with_summary("Removes one of your aliases."),
+        RequiresLogin,
+    ));
+}
+
+#[derive(Component, Default)]
+struct AliasCommand;
+
+#[derive(Component, Default)]
+struct UnaliasCommand;
+
+/// How many aliases a player can have.
+const MAX_ALIASES: usize = 100;
+
+/// Shortcuts a player has made for themselves, from what they type to the command it runs.
+#[derive(Component, Debug, Default, Clone)]
+pub struct Aliases(pub BTreeMap<String, String>);
+
+/// Replaces a player's aliases with what they stand for. Aliases aren't expanded again, so they
+/// can't loop.
+fn expand_aliases(
+    mut comms: Query<&mut PlayerCommand>,
+    conns: Query<&PlayerConnection>,
+    aliases: Query<&Aliases>,
+) {
+    for mut command in comms.iter_mut() {
+        let Some(expansion) = conns
+            .get(command.conn)
+            .ok()
+            .and_then(|conn| aliases.get(conn.object).ok())
+            .and_then(|aliases| aliases.0.get(&command.inner.command))
+        else {
+            continue;
+        };
+
+        let line = format!("{} {}", expansion, command.inner.text);
+        command.inner = CommandInner::parse(&line);
+    }
+}
+
+fn handle_alias(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<AliasCommand>>,
+    conns: Query<&PlayerConnection>,
+    mut aliases: Query<&mut Aliases>,
+    handlers: Query<&CommandInfo>,
+) {
+    for command in comms.iter() {
+        let Ok(player) = conns.get(command.conn).map(|conn| conn.object) else {
+            continue;
+        };
+        let Ok(mut aliases) = aliases.get_mut(player) else {
+            continue;
+        };
+        let name = command.args.text("name");
+        let expansion = command.args.text("command");
+
+        let result = match (name, expansion) {
+            ("", _) if aliases.0.is_empty() => Ok("You have no aliases.".to_owned()),
+            ("", _) => Ok(format!(
+                "Your aliases:\n{}",
+                aliases
+                    .0
+                    .iter()
+                    .map(|(name, expansion)| format!("  {} = {}", escape(name), escape(expansion)))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            )),
+            (name, "") => aliases
+                .0
+                .get(name)
+                .map(|expansion| format!("{} = {}", escape(name), escape(expansion)))
+                .ok_or_else(|| format!("You have no alias {}.", escape(name))),
+            (name, _) if name.contains(char::is_whitespace) => {
+                Err("Aliases can't have spaces in them.".to_owned())
+            }
+            (name, _)
+                if PREFIX_COMMANDS
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix)) =>
+            {
+                Err(format!(
+                    "Aliases can't start with {}.",
+                    PREFIX_COMMANDS.join(" or ")
+                ))
+            }
+            (name, _)
+                if handlers.iter().any(|info| {
+                    info.command == name || info.aliases.iter().any(|alias| alias == name)
+                }) =>
+            {
+                Err(format!("{} is already a command.", escape(name)))
+            }
+            (name, _) if !aliases.0.contains_key(name) && aliases.0.len() >= MAX_ALIASES => {
+                Err(format!("You can't have more than {} aliases.", MAX_ALIASES))
+            }
+            (name, expansion) => {
+                aliases.0.insert(name.to_owned(), expansion.to_owned());
+                Ok(format!(
+                    "Aliased {} to {}.",
+                    escape(name),
+                    escape(expansion)
+                ))
+            }
+        };
+
+        send(&mut commands, command.conn, result);
+    }
+}
+
+fn handle_unalias(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<UnaliasCommand>>,
+    conns: Query<&PlayerConnection>,
+    mut aliases: Query<&mut Aliases>,
+) {
+    for command in comms.iter() {
+        let Ok(player) = conns.get(command.conn).map(|conn| conn.object) else {
+            continue;
+        };
+        let Ok(mut aliases) = aliases.get_mut(player) else {
+            continue;
+        };
+        let name = command.args.text("name");
+
+        let result = match aliases.0.remove(name) {
+            Some(_) => Ok(format!("Removed alias {}.", escape(name))),
+            None => Err(format!("You have no alias {}.", escape(name))),
+        };
+        send(&mut commands, command.conn, result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::alias::AliasPlugin;
+    use crate::interact::InteractPlugin;
+    use crate::login::LoginPlugin;
+    use crate::PlayerCommand;
+
+    fn alias_app() -> (
+        bevy::app::App,
+        bevy::prelude::Entity,
+        std::sync::mpsc::Receiver<crate::ConnectionMessageEvent>,
+    ) {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((AliasPlugin, LoginPlugin, InteractPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        (app, conn, rx)
+    }
+
+    #[test]
+    fn aliases_run_their_command() {
+        let (mut app, conn, rx) = alias_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("alias", vec!["peek", "look"], conn));
+        app.update();
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("peek", vec![], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+    }
+
+    #[test]
+    fn aliases_cant_shadow_commands() {
+        let (mut app, conn, rx) = alias_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("alias", vec!["look", "logout"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+    }
+
+    #[test]
+    fn unalias_removes_alias() {
+        let (mut app, conn, rx) = alias_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("alias", vec!["peek", "look"], conn));
+        app.update();
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("unalias", vec!["peek"], conn));
+        app.update();
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("peek", vec![], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+    }
+}
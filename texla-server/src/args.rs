@@ -0,0 +1,359 @@
+use std::fmt;
+
+use bevy::prelude::*;
+
+use crate::markup::escape;
+use crate::{or_list, CommandInner};
+
+/// What sort of value an argument takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgKind {
+    /// One argument, as typed.
+    Text,
+    /// Everything after the arguments before it, pipes and all, for commands that take free text.
+    /// Only makes sense as a command's last argument.
+    Rest,
+    Integer,
+    /// One of a fixed set of words, like `text` or `json`.
+    Choice(&'static [&'static str]),
+    /// The username of any player, online or not.
+    Player,
+    /// Anything the player could name where they stand, like `here`, `me`, or `#<id>`.
+    Object,
+}
+
+/// One argument in a [`CommandHandler`](crate::CommandHandler)'s schema.
+#[derive(Debug, Clone)]
+pub struct Arg {
+    name: &'static str,
+    kind: ArgKind,
+    optional: bool,
+    variadic: bool,
+}
+
+impl Arg {
+    pub fn new(name: &'static str, kind: ArgKind) -> Self {
+        Self {
+            name,
+            kind,
+            optional: false,
+            variadic: false,
+        }
+    }
+
+    /// Lets the argument be left out. Only arguments after it can be optional too.
+    pub fn optional(mut self) -> Self {
+        self.optional = true;
+        self
+    }
+
+    /// Makes the argument take every argument left over. Only makes sense last.
+    pub fn variadic(mut self) -> Self {
+        self.variadic = true;
+        self
+    }
+}
+
+impl fmt::Display for Arg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let dots = if self.variadic { "..." } else { "" };
+        write!(f, "<{}>{}", self.name, dots)
+    }
+}
+
+/// Writes a schema out the way it's typed, like `<object> | <key> [| <value>]`.
+pub fn usage(schema: &[Arg]) -> String {
+    schema
+        .iter()
+        .enumerate()
+        .map(|(i, arg)| {
+            let pipe = if i > 0 { "| " } else { "" };
+            if arg.optional {
+                format!("[{}{}]", pipe, arg)
+            } else {
+                format!("{}{}", pipe, arg)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgValue {
+    Text(String),
+    Integer(i64),
+    Entity(Entity),
+}
+
+/// A command's arguments once they've been checked against its schema, by name.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Args(Vec<(&'static str, ArgValue)>);
+
+impl Args {
+    /// The first value given for an argument, if any.
+    pub fn get(&self, name: &str) -> Option<&ArgValue> {
+        self.0
+            .iter()
+            .find(|(arg, _)| *arg == name)
+            .map(|(_, value)| value)
+    }
+
+    pub fn has(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// A text argument, or nothing if it was left out.
+    pub fn text(&self, name: &str) -> &str {
+        match self.get(name) {
+            Some(ArgValue::Text(text)) => text,
+            _ => "",
+        }
+    }
+
+    /// An integer argument. Panics if it was left out, so check optional ones with [`Args::has`]
+    /// first.
+    pub fn integer(&self, name: &str) -> i64 {
+        match self.get(name) {
+            Some(ArgValue::Integer(integer)) => *integer,
+            _ => panic!("No integer argument {}", name),
+        }
+    }
+
+    /// Every value of a variadic text argument.
+    pub fn texts<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.0.iter().filter_map(move |(arg, value)| match value {
+            ArgValue::Text(text) if *arg == name => Some(text.as_str()),
+            _ => None,
+        })
+    }
+
+    /// A player or object argument. Panics if it was left out, so check optional ones with
+    /// [`Args::has`] first.
+    pub fn entity(&self, name: &str) -> Entity {
+        match self.get(name) {
+            Some(ArgValue::Entity(entity)) => *entity,
+            _ => panic!("No entity argument {}", name),
+        }
+    }
+}
+
+/// Why a command's arguments didn't fit its schema.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArgError {
+    /// Too many or too few of them, so the player should be shown the usage.
+    Usage,
+    /// One of them didn't make sense.
+    Invalid(String),
+}
+
+/// Checks a command's arguments against `schema`, looking up players and objects with `find`. A
+/// required argument typed as nothing counts as missing.
+pub fn parse_args(
+    schema: &[Arg],
+    inner: &CommandInner,
+    find: impl Fn(ArgKind, &str) -> Result<Entity, String>,
+) -> Result<Args, ArgError> {
+    // Free text is taken as typed, quotes, backslashes and all
+    let (split, rest) = match schema.iter().position(|arg| arg.kind == ArgKind::Rest) {
+        Some(count) => inner.split_leading(count),
+        None => inner.split().map(|split| (split, String::new())),
+    }
+    .map_err(|err| ArgError::Invalid(err.to_string()))?;
+    // Typing nothing at all still splits into one empty argument
+    let mut given = split
+        .iter()
+        .map(String::as_str)
+        .filter(|_| !inner.text.is_empty());
+    let mut args = Args::default();
+
+    for arg in schema {
+        if arg.kind == ArgKind::Rest {
+            if rest.is_empty() && !arg.optional {
+                return Err(ArgError::Usage);
+            }
+            args.0.push((arg.name, ArgValue::Text(rest)));
+            return Ok(args);
+        }
+
+        let taken = if arg.variadic {
+            given.by_ref().collect::<Vec<_>>()
+        } else {
+            given.next().into_iter().collect()
+        };
+        if taken.iter().all(|value| value.is_empty()) {
+            if arg.optional {
+                continue;
+            }
+            return Err(ArgError::Usage);
+        }
+
+        for value in taken {
+            let value = match arg.kind {
+                ArgKind::Text | ArgKind::Rest => ArgValue::Text(value.to_owned()),
+                ArgKind::Choice(choices) => {
+                    if !choices.contains(&value) {
+                        return Err(ArgError::Invalid(format!(
+                            "{} isn't {}.",
+                            escape(value),
+                            or_list(choices)
+                        )));
+                    }
+                    ArgValue::Text(value.to_owned())
+                }
+                ArgKind::Integer => ArgValue::Integer(value.parse().map_err(|_| {
+                    ArgError::Invalid(format!("{} isn't a whole number.", escape(value)))
+                })?),
+                ArgKind::Player | ArgKind::Object => {
+                    ArgValue::Entity(find(arg.kind, value).map_err(ArgError::Invalid)?)
+                }
+            };
+            args.0.push((arg.name, value));
+        }
+    }
+
+    if given.next().is_some() {
+        return Err(ArgError::Usage);
+    }
+
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::args::{parse_args, usage, Arg, ArgError, ArgKind, ArgValue, Args};
+    use crate::CommandInner;
+
+    fn parse(schema: &[Arg], str: &str) -> Result<Args, ArgError> {
+        parse_args(schema, &CommandInner::parse(str), |kind, name| {
+            match (kind, name) {
+                (ArgKind::Player, "alice") => Ok(Entity::from_raw(1)),
+                _ => Err(format!("No {}.", name)),
+            }
+        })
+    }
+
+    #[test]
+    fn usage_is_written_like_it_is_typed() {
+        let schema = [
+            Arg::new("object", ArgKind::Object),
+            Arg::new("key", ArgKind::Text),
+            Arg::new("value", ArgKind::Text).optional(),
+        ];
+
+        assert_eq!(usage(&schema), "<object> | <key> [| <value>]");
+    }
+
+    #[test]
+    fn args_are_counted() {
+        let schema = [
+            Arg::new("key", ArgKind::Text),
+            Arg::new("value", ArgKind::Text).optional(),
+        ];
+
+        assert_eq!(parse(&schema, "set"), Err(ArgError::Usage));
+        assert_eq!(parse(&schema, "set | value"), Err(ArgError::Usage));
+        assert_eq!(parse(&schema, "set a | b | c"), Err(ArgError::Usage));
+
+        let args = parse(&schema, "set key").unwrap();
+        assert_eq!(args.text("key"), "key");
+        assert!(!args.has("value"));
+        assert_eq!(args.text("value"), "");
+    }
+
+    #[test]
+    fn args_are_parsed() {
+        let schema = [
+            Arg::new("player", ArgKind::Player),
+            Arg::new("count", ArgKind::Integer),
+            Arg::new("rest", ArgKind::Text).optional().variadic(),
+        ];
+
+        let args = parse(&schema, "give alice | 3 | a | b").unwrap();
+        assert_eq!(args.entity("player"), Entity::from_raw(1));
+        assert_eq!(args.get("count"), Some(&ArgValue::Integer(3)));
+        assert_eq!(args.integer("count"), 3);
+        assert_eq!(args.texts("rest").collect::<Vec<_>>(), vec!["a", "b"]);
+
+        assert_eq!(
+            parse(&schema, "give bob | 3"),
+            Err(ArgError::Invalid("No bob.".to_owned()))
+        );
+        assert_eq!(
+            parse(&schema, "give alice | three"),
+            Err(ArgError::Invalid("three isn't a whole number.".to_owned()))
+        );
+    }
+
+    #[test]
+    fn choices_are_checked() {
+        let schema = [Arg::new("format", ArgKind::Choice(&["text", "json"]))];
+
+        assert_eq!(
+            parse(&schema, "protocol json").unwrap().text("format"),
+            "json"
+        );
+        assert_eq!(
+            parse(&schema, "protocol xml"),
+            Err(ArgError::Invalid("xml isn't text or json.".to_owned()))
+        );
+    }
+
+    #[test]
+    fn rest_takes_everything() {
+        let schema = [Arg::new("message", ArgKind::Rest)];
+
+        assert_eq!(
+            parse(&schema, "say  hi | there ").unwrap().text("message"),
+            "hi | there"
+        );
+        assert_eq!(parse(&schema, "say"), Err(ArgError::Usage));
+    }
+
+    #[test]
+    fn rest_is_not_split() {
+        let schema = [Arg::new("message", ArgKind::Rest)];
+
+        assert_eq!(
+            parse(&schema, r#"say I'm 6" tall"#)
+                .unwrap()
+                .text("message"),
+            r#"I'm 6" tall"#
+        );
+        assert_eq!(parse(&schema, r"say C:\").unwrap().text("message"), r"C:\");
+    }
+
+    #[test]
+    fn rest_follows_other_args() {
+        let schema = [
+            Arg::new("player", ArgKind::Player),
+            Arg::new("message", ArgKind::Rest),
+        ];
+
+        let args = parse(&schema, r#"page alice | a | b "c"#).unwrap();
+        assert_eq!(args.entity("player"), Entity::from_raw(1));
+        assert_eq!(args.text("message"), r#"a | b "c"#);
+        assert_eq!(parse(&schema, "page alice"), Err(ArgError::Usage));
+        assert_eq!(parse(&schema, "page alice |"), Err(ArgError::Usage));
+    }
+
+    #[test]
+    fn malformed_args_are_invalid() {
+        let schema = [Arg::new("key", ArgKind::Text)];
+
+        assert_eq!(
+            parse(&schema, r#"set "key"#),
+            Err(ArgError::Invalid("Missing a closing quote.".to_owned()))
+        );
+    }
+
+    #[test]
+    fn escaped_trailing_spaces_are_kept() {
+        let schema = [Arg::new("key", ArgKind::Text)];
+
+        assert_eq!(parse(&schema, r"set key\ ").unwrap().text("key"), "key ");
+        assert_eq!(parse(&schema, "set key  ").unwrap().text("key"), "key");
+        assert_eq!(parse(&schema, "set   "), Err(ArgError::Usage));
+    }
+}
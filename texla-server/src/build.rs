@@ -0,0 +1,738 @@
+use bevy::ecs::system::SystemParam;
+use bevy::prelude::*;
+use bevy::utils::HashSet;
+
+use crate::interact::{look, LookBundle};
+use crate::markup::escape;
+use crate::prelude::*;
+use crate::SpawnRoom;
+
+pub struct BuildPlugin;
+
+impl Plugin for BuildPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                (
+                    preprocess_commands::<CreateCommand>,
+                    preprocess_commands::<DigCommand>,
+                    preprocess_commands::<NameCommand>,
+                    preprocess_commands::<SetCommand>,
+                    preprocess_commands::<DescribeCommand>,
+                    preprocess_commands::<DestroyCommand>,
+                )
+                    .in_set(PreprocessCommandsSet),
+                (
+                    handle_create,
+                    handle_dig,
+                    handle_name,
+                    handle_set,
+                    handle_describe,
+                    handle_destroy,
+                )
+                    .in_set(HandleCommandsSet),
+            ),
+        );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        CommandHandler::<CreateCommand>::new("create")
+            .with_args([Arg::new("name", ArgKind::Text)])
+            .with_summary("Creates an object in the room you're in."),
+        RequiresLogin,
+        RequiresRole(Role::Builder),
+    ));
+    commands.spawn((
+        CommandHandler::<DigCommand>::new("dig").with_args([
+            Arg::new("exit", ArgKind::Text),
+            Arg::new("new room or #id", ArgKind::Text),
+            Arg::new("return exit", ArgKind::Text).optional(),
+        ])
+        .with_summary("Makes an exit to a new room, or to an existing one by its #id.")
+        .with_help(
+            "Name an exit `name;alias;alias` to give it aliases. Naming a return exit digs one \
+            back the other way too.",
+        ),
+        RequiresLogin,
+        RequiresRole(Role::Builder),
+    ));
+    commands.spawn((
+        CommandHandler::<NameCommand>::new("name")
+            .with_args([
+                Arg::new("object", ArgKind::Object),
+                Arg::new("name", ArgKind::Text),
+            ])
+            .with_summary("Renames an object."),
+        RequiresLogin,
+        RequiresRole(Role::Builder),
+    ));
+    commands.spawn((
+        CommandHandler::<SetCommand>::new("set")
+            .with_args([
+                Arg::new("object", ArgKind::Object),
+                Arg::new("key", ArgKind::Text),
+                Arg::new("value", ArgKind::Text).optional(),
+            ])
+            .with_summary("Sets a property on an object, or clears it if there's no value."),
+        RequiresLogin,
+        RequiresRole(Role::Builder),
+    ));
+    commands.spawn((
+        CommandHandler::<DescribeCommand>::new("describe")
+            .with_args([
+                Arg::new("object", ArgKind::Object),
+                Arg::new("description", ArgKind::Rest).optional(),
+            ])
+            .with_summary("Sets what an object looks like, or clears it."),
+        RequiresLogin,
+        RequiresRole(Role::Builder),
+    ));
+    commands.spawn((
+        CommandHandler::<DestroyCommand>::new("destroy")
+            .with_args([Arg::new("object", ArgKind::Object)])
+            .with_summary("Destroys an object and everything in it.")
+            .with_help("Players are never destroyed, just sent back to the spawn room."),
+        RequiresLogin,
+        RequiresRole(Role::Builder),
+    ));
+}
+
+#[derive(Component, Default)]
+struct CreateCommand;
+
+#[derive(Component, Default)]
+struct DigCommand;
+
+#[derive(Component, Default)]
+struct NameCommand;
+
+#[derive(Component, Default)]
+struct SetCommand;
+
+#[derive(Component, Default)]
+struct DescribeCommand;
+
+#[derive(Component, Default)]
+struct DestroyCommand;
+
+/// How objects are written in messages so that they can be typed back in as `#<id>`.
+pub fn object_ref(entity: Entity) -> String {
+    format!("#{}", entity.to_bits())
+}
+
+/// Resolves the names players type into the objects they mean, relative to where they stand.
+#[derive(SystemParam)]
+pub struct ObjectRefs<'w, 's> {
+    conns: Query<'w, 's, &'static PlayerConnection>,
+    parents: Query<'w, 's, &'static Parent>,
+    children: Query<'w, 's, &'static Children>,
+    objects: Query<'w, 's, (Option<&'static Name>, Option<&'static Exit>), With<Object>>,
+}
+
+impl ObjectRefs<'_, '_> {
+    /// The player object behind a connection and the room it's standing in.
+    pub fn locate(&self, conn: Entity) -> Result<(Entity, Entity), String> {
+        let player = self
+            .conns
+            .get(conn)
+            .map_err(|_| "You must be logged in to do that.".to_owned())?
+            .object;
+        let room = self
+            .parents
+            .get(player)
+            .map_err(|_| "You aren't anywhere.".to_owned())?
+            .get();
+        Ok((player, room))
+    }
+
+    /// Finds `here`, `me`, `#<id>`, or anything named `name` in the room or carried by the player.
+    pub fn resolve(&self, conn: Entity, name: &str) -> Result<Entity, String> {
+        let (player, room) = self.locate(conn)?;
+        match name {
+            "" => Err("You must name an object.".to_owned()),
+            "here" => Ok(room),
+            "me" => Ok(player),
+            _ => {
+                let found = if let Some(id) = name.strip_prefix('#') {
+                    id.parse::<u64>()
+                        .ok()
+                        .and_then(|bits| Entity::try_from_bits(bits).ok())
+                        .filter(|entity| self.objects.contains(*entity))
+                } else {
+                    [player, room]
+                        .into_iter()
+                        .flat_map(|entity| self.children.get(entity).into_iter().flatten())
+                        .copied()
+                        .find(|child| {
+                            self.objects.get(*child).is_ok_and(|(child_name, exit)| {
+                                child_name.is_some_and(|n| n.eq_ignore_ascii_case(name))
+                                    || exit.is_some_and(|exit| exit.matches(name))
+                            })
+                        })
+                };
+                found.ok_or_else(|| format!("There is no {} here.", escape(name)))
+            }
+        }
+    }
+}
+
+/// Splits `name;alias;alias` as typed into `dig` into an exit name and its aliases.
+fn parse_exit_name(str: &str) -> (String, Vec<String>) {
+    let mut parts = str
+        .split(";")
+        .map(|s| s.trim().to_owned())
+        .filter(|s| !s.is_empty());
+    let name = parts.next().unwrap_or_default();
+    (name, parts.collect())
+}
+
+fn handle_create(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<CreateCommand>>,
+    refs: ObjectRefs,
+) {
+    for command in comms.iter() {
+        let name = command.args.text("name");
+        let (_, room) = match refs.locate(command.conn) {
+            Ok(located) => located,
+            Err(err) => {
+                send(&mut commands, command.conn, Err(err));
+                continue;
+            }
+        };
+        let object = commands
+            .spawn((Name::new(name.to_owned()), Object::default()))
+            .set_parent(room)
+            .id();
+
+        send(
+            &mut commands,
+            command.conn,
+            Ok(format!(
+                "Created {} ({}).",
+                escape(name),
+                object_ref(object)
+            )),
+        );
+    }
+}
+
+fn handle_dig(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<DigCommand>>,
+    refs: ObjectRefs,
+    players: Query<(), With<Player>>,
+    exits: Query<(), With<Exit>>,
+) {
+    for command in comms.iter() {
+        let (exit_name, exit_aliases) = parse_exit_name(command.args.text("exit"));
+        let destination_name = command.args.text("new room or #id");
+        if exit_name.is_empty() {
+            send(
+                &mut commands,
+                command.conn,
+                Err("Your exit needs a name.".to_owned()),
+            );
+            continue;
+        }
+
+        let (_, room) = match refs.locate(command.conn) {
+            Ok(located) => located,
+            Err(err) => {
+                send(&mut commands, command.conn, Err(err));
+                continue;
+            }
+        };
+        let destination = if destination_name.starts_with('#') {
+            match refs.resolve(command.conn, destination_name) {
+                Ok(destination) if players.contains(destination) || exits.contains(destination) => {
+                    send(
+                        &mut commands,
+                        command.conn,
+                        Err(format!("{} isn't a room.", object_ref(destination))),
+                    );
+                    continue;
+                }
+                Ok(destination) => destination,
+                Err(err) => {
+                    send(&mut commands, command.conn, Err(err));
+                    continue;
+                }
+            }
+        } else {
+            commands
+                .spawn((Name::new(destination_name.to_owned()), Object::default()))
+                .id()
+        };
+
+        commands
+            .spawn((
+                Object::default(),
+                Exit {
+                    name: exit_name.clone(),
+                    aliases: exit_aliases,
+                    destination,
+                },
+            ))
+            .set_parent(room);
+
+        let mut message = format!(
+            "Dug {} to {} ({}).",
+            escape(&exit_name),
+            escape(destination_name),
+            object_ref(destination)
+        );
+
+        if command.args.has("return exit") {
+            let (return_name, return_aliases) = parse_exit_name(command.args.text("return exit"));
+            if !return_name.is_empty() {
+                commands
+                    .spawn((
+                        Object::default(),
+                        Exit {
+                            name: return_name.clone(),
+                            aliases: return_aliases,
+                            destination: room,
+                        },
+                    ))
+                    .set_parent(destination);
+                message.push_str(&format!(" Dug {} back.", escape(&return_name)));
+            }
+        }
+
+        send(&mut commands, command.conn, Ok(message));
+    }
+}
+
+fn handle_name(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<NameCommand>>,
+    players: Query<(), With<Player>>,
+) {
+    for command in comms.iter() {
+        let target = command.args.entity("object");
+
+        if players.contains(target) {
+            send(
+                &mut commands,
+                command.conn,
+                Err("You can't rename a player.".to_owned()),
+            );
+            continue;
+        }
+
+        let name = command.args.text("name").to_owned();
+        let exit_name = name.clone();
+        commands
+            .entity(target)
+            .insert(Name::new(name.clone()))
+            .entry::<Exit>()
+            .and_modify(move |mut exit| exit.name = exit_name);
+
+        send(
+            &mut commands,
+            command.conn,
+            Ok(format!("Named {} {}.", object_ref(target), escape(&name))),
+        );
+    }
+}
+
+fn handle_set(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<SetCommand>>,
+    mut objects: Query<&mut Object>,
+) {
+    for command in comms.iter() {
+        let target = command.args.entity("object");
+        let key = command.args.text("key").to_owned();
+        let value = command.args.text("value").to_owned();
+        send(
+            &mut commands,
+            command.conn,
+            set_property(&mut objects, target, key, value),
+        );
+    }
+}
+
+fn handle_describe(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<DescribeCommand>>,
+    mut objects: Query<&mut Object>,
+) {
+    for command in comms.iter() {
+        let target = command.args.entity("object");
+        let description = command.args.text("description").to_owned();
+        send(
+            &mut commands,
+            command.conn,
+            set_property(&mut objects, target, "description".to_owned(), description),
+        );
+    }
+}
+
+/// Sets a property on an object, or clears it if `value` is empty.
+fn set_property(
+    objects: &mut Query<&mut Object>,
+    target: Entity,
+    key: String,
+    value: String,
+) -> Result<String, String> {
+    let Ok(mut obj) = objects.get_mut(target) else {
+        return Err(format!("{} is gone.", object_ref(target)));
+    };
+    if value.is_empty() {
+        obj.properties.remove(&key);
+        Ok(format!(
+            "Cleared {} on {}.",
+            escape(&key),
+            object_ref(target)
+        ))
+    } else {
+        obj.properties.insert(key.clone(), value);
+        Ok(format!("Set {} on {}.", escape(&key), object_ref(target)))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn handle_destroy(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<DestroyCommand>>,
+    spawn_room: Res<SpawnRoom>,
+    players: Query<(), With<Player>>,
+    children: Query<&Children>,
+    exits: Query<(Entity, &Exit)>,
+    by_player: Res<ConnectionsByPlayer>,
+    looks: Query<LookBundle>,
+    exit_looks: Query<&Exit>,
+) {
+    for command in comms.iter() {
+        let target = command.args.entity("object");
+
+        if players.contains(target) {
+            send(
+                &mut commands,
+                command.conn,
+                Err("You can't destroy a player.".to_owned()),
+            );
+            continue;
+        }
+
+        let doomed = std::iter::once(target)
+            .chain(children.iter_descendants(target))
+            .collect::<HashSet<_>>();
+
+        // The spawn room can be set to anything, including something inside the target
+        if doomed.contains(&spawn_room.0) {
+            send(
+                &mut commands,
+                command.conn,
+                Err("You can't destroy the spawn room or anything it's in.".to_owned()),
+            );
+            continue;
+        }
+
+        // Players are never destroyed along with their surroundings, just sent back to spawn
+        for player in doomed.iter().filter(|e| players.contains(**e)) {
+            commands.entity(*player).set_parent(spawn_room.0);
+            if let Some(&conn) = by_player.of(*player).first() {
+                send_as(
+                    &mut commands,
+                    conn,
+                    MessageKind::Room,
+                    Ok(format!(
+                        "The world dissolves around you.\n{}",
+                        look(looks.get(spawn_room.0).unwrap(), &exit_looks)
+                    )),
+                );
+            }
+        }
+
+        // Exits leading into the destroyed objects would otherwise dangle
+        for (exit, _) in exits
+            .iter()
+            .filter(|(e, exit)| doomed.contains(&exit.destination) && !doomed.contains(e))
+        {
+            commands.entity(exit).despawn_recursive();
+        }
+
+        commands.entity(target).despawn_recursive();
+
+        send(
+            &mut commands,
+            command.conn,
+            Ok(format!("Destroyed {}.", object_ref(target))),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::build::{object_ref, BuildPlugin};
+    use crate::login::LoginPlugin;
+    use crate::movement::Exit;
+    use crate::roles::{Role, Roles};
+    use crate::{Object, Player, PlayerCommand, SpawnRoom};
+
+    fn builder_app() -> (
+        App,
+        Entity,
+        std::sync::mpsc::Receiver<crate::ConnectionMessageEvent>,
+    ) {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, BuildPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        // Demote the first account from admin so only the builder role is being tested
+        let world = app.world_mut();
+        let mut roles = world.query::<&mut Roles>().single_mut(world);
+        roles.0.clear();
+        roles.0.insert(Role::Builder);
+
+        (app, conn, rx)
+    }
+
+    fn named(app: &mut App, name: &str) -> Option<Entity> {
+        let world = app.world_mut();
+        world
+            .query::<(Entity, &Name)>()
+            .iter(world)
+            .find(|(_, n)| n.as_str() == name)
+            .map(|(e, _)| e)
+    }
+
+    #[test]
+    fn building_without_permission_fails() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, BuildPlugin));
+
+        // The first account to register is an admin
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["admin", "password"],
+            conns[0],
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("create", vec!["rock"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        assert!(named(&mut app, "rock").is_none());
+    }
+
+    #[test]
+    fn create_works() {
+        let (mut app, conn, rx) = builder_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("create", vec!["rock"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+        let rock = named(&mut app, "rock").unwrap();
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        assert_eq!(
+            app.world().get::<Parent>(rock).map(|p| p.get()),
+            Some(spawn_room)
+        );
+    }
+
+    #[test]
+    fn dig_works() {
+        let (mut app, conn, rx) = builder_app();
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "dig",
+            vec!["north;n", "Kitchen", "south"],
+            conn,
+        ));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+        let kitchen = named(&mut app, "Kitchen").unwrap();
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        let world = app.world_mut();
+        let exits = world
+            .query::<(&Exit, &Parent)>()
+            .iter(world)
+            .map(|(exit, parent)| (exit.name.clone(), exit.destination, parent.get()))
+            .collect::<Vec<_>>();
+        assert!(exits.contains(&("north".to_owned(), kitchen, spawn_room)));
+        assert!(exits.contains(&("south".to_owned(), spawn_room, kitchen)));
+    }
+
+    #[test]
+    fn digging_into_a_player_fails() {
+        let (mut app, conn, rx) = builder_app();
+
+        let world = app.world_mut();
+        let player = world.query_filtered::<Entity, With<Player>>().single(world);
+        let player_ref = object_ref(player);
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("dig", vec!["north", &player_ref], conn));
+        app.update();
+
+        let expected = format!("{} isn't a room.", player_ref);
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == expected)));
+        let world = app.world_mut();
+        assert_eq!(world.query::<&Exit>().iter(world).count(), 0);
+    }
+
+    #[test]
+    fn building_from_nowhere_fails() {
+        let (mut app, conn, rx) = builder_app();
+
+        let world = app.world_mut();
+        let player = world.query_filtered::<Entity, With<Player>>().single(world);
+        world.entity_mut(player).remove_parent();
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "set",
+            vec!["here", "key", "value"],
+            conn,
+        ));
+        app.update();
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == "You aren't anywhere.")));
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("create", vec!["rock"], conn));
+        app.update();
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == "You aren't anywhere.")));
+    }
+
+    #[test]
+    fn set_and_clear_works() {
+        let (mut app, conn, rx) = builder_app();
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "set",
+            vec!["here", "smell", "musty"],
+            conn,
+        ));
+        app.update();
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        assert_eq!(
+            app.world()
+                .get::<Object>(spawn_room)
+                .unwrap()
+                .properties
+                .get("smell"),
+            Some(&"musty".to_owned())
+        );
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("set", vec!["here", "smell"], conn));
+        app.update();
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+
+        assert!(!app
+            .world()
+            .get::<Object>(spawn_room)
+            .unwrap()
+            .properties
+            .contains_key("smell"));
+    }
+
+    #[test]
+    fn destroy_rehomes_players() {
+        let (mut app, conn, rx) = builder_app();
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "dig",
+            vec!["north", "Kitchen", "south"],
+            conn,
+        ));
+        app.update();
+        rx.try_recv().unwrap();
+
+        let kitchen = named(&mut app, "Kitchen").unwrap();
+        let world = app.world_mut();
+        let player = world.query_filtered::<Entity, With<Player>>().single(world);
+        world.entity_mut(player).set_parent(kitchen);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "destroy",
+            vec![&object_ref(kitchen)],
+            conn,
+        ));
+        app.update();
+
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        assert!(app.world().get_entity(kitchen).is_err());
+        assert_eq!(
+            app.world().get::<Parent>(player).map(|p| p.get()),
+            Some(spawn_room)
+        );
+        let world = app.world_mut();
+        assert_eq!(world.query::<&Exit>().iter(world).count(), 0);
+    }
+
+    #[test]
+    fn destroying_spawn_room_fails() {
+        let (mut app, conn, rx) = builder_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("destroy", vec!["here"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+    }
+
+    #[test]
+    fn destroying_what_the_spawn_room_is_in_fails() {
+        let (mut app, conn, rx) = builder_app();
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "dig",
+            vec!["north", "Kitchen", "south"],
+            conn,
+        ));
+        app.update();
+        rx.try_recv().unwrap();
+
+        let kitchen = named(&mut app, "Kitchen").unwrap();
+        let world = app.world_mut();
+        let pantry = world.spawn(Object::default()).set_parent(kitchen).id();
+        world.insert_resource(SpawnRoom(pantry));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "destroy",
+            vec![&object_ref(kitchen)],
+            conn,
+        ));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        assert!(app.world().get_entity(kitchen).is_ok());
+        assert!(app.world().get_entity(pantry).is_ok());
+    }
+}
@@ -0,0 +1,623 @@
+use bevy::prelude::*;
+
+use crate::mail::PendingMail;
+use crate::markup::escape;
+use crate::prelude::*;
+
+pub struct ChatPlugin;
+
+impl Plugin for ChatPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                (
+                    preprocess_commands::<SayCommand>,
+                    preprocess_commands::<EmoteCommand>,
+                    preprocess_commands::<PoseCommand>,
+                    preprocess_commands::<WhisperCommand>,
+                    preprocess_commands::<PageCommand>,
+                )
+                    .in_set(PreprocessCommandsSet),
+                (
+                    handle_say,
+                    handle_emote,
+                    handle_pose,
+                    handle_whisper,
+                    handle_page,
+                )
+                    .in_set(HandleCommandsSet),
+            ),
+        );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        CommandHandler::<SayCommand>::new("say")
+            .with_args([Arg::new("message", ArgKind::Rest)])
+            .with_summary("Says something to everyone in the room."),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<EmoteCommand>::new("emote")
+            .with_aliases([":"])
+            .with_args([Arg::new("action", ArgKind::Rest)])
+            .with_summary("Acts something out, like `emote waves` or `:waves`."),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<PoseCommand>::new("pose")
+            .with_args([Arg::new("text", ArgKind::Rest)])
+            .with_summary("Like emote, but without a space after your name."),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<WhisperCommand>::new("whisper")
+            .with_args([
+                Arg::new("player", ArgKind::Player),
+                Arg::new("message", ArgKind::Rest),
+            ])
+            .with_summary("Says something only one person in the room can hear."),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<PageCommand>::new("page")
+            .with_args([
+                Arg::new("player", ArgKind::Player),
+                Arg::new("message", ArgKind::Rest),
+            ])
+            .with_summary("Sends a message to a player wherever they are.")
+            .with_help("If they're offline, you'll be offered to send it as mail instead."),
+        RequiresLogin,
+    ));
+}
+
+#[derive(Component, Default)]
+struct SayCommand;
+
+#[derive(Component, Default)]
+struct EmoteCommand;
+
+#[derive(Component, Default)]
+struct PoseCommand;
+
+#[derive(Component, Default)]
+struct WhisperCommand;
+
+#[derive(Component, Default)]
+struct PageCommand;
+
+const NOT_LOGGED_IN: &str = "You must be logged in to do that.";
+
+/// For a player found when the command was checked who's been removed since.
+const GONE: &str = "That player isn't around any more.";
+
+/// The player who sent a command, and the room they're saying it in.
+struct Speaker<'a> {
+    player: Entity,
+    info: &'a Player,
+    room: Entity,
+}
+
+/// The player whose connection sent `command` and where they are, or why they can't speak.
+fn speaker<'a>(
+    command: &PlayerCommand,
+    conns: &Query<&PlayerConnection>,
+    players: &'a Query<&Player>,
+    player_parents: &Query<&Parent, With<Player>>,
+) -> Result<Speaker<'a>, String> {
+    let Ok((player, info)) = conns
+        .get(command.conn)
+        .and_then(|conn| players.get(conn.object).map(|info| (conn.object, info)))
+    else {
+        return Err(NOT_LOGGED_IN.to_owned());
+    };
+    let Ok(room) = player_parents.get(player).map(|parent| parent.get()) else {
+        return Err("You aren't anywhere.".to_owned());
+    };
+    Ok(Speaker { player, info, room })
+}
+
+/// Sends `first_person` to the speaker and `third_person` to everyone else in their room.
+fn speak(
+    commands: &mut Commands,
+    command: &PlayerCommand,
+    speaker: &Speaker,
+    room_conns: &RoomConnections,
+    first_person: String,
+    third_person: String,
+) {
+    broadcast_as(
+        commands,
+        room_conns
+            .in_room(speaker.room)
+            .filter(|(_, player)| *player != speaker.player)
+            .map(|(conn, _)| conn),
+        MessageKind::Chat,
+        Ok(third_person),
+    );
+    send_as(commands, command.conn, MessageKind::Chat, Ok(first_person));
+}
+
+fn handle_say(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<SayCommand>>,
+    conns: Query<&PlayerConnection>,
+    players: Query<&Player>,
+    player_parents: Query<&Parent, With<Player>>,
+    room_conns: RoomConnections,
+) {
+    for command in comms.iter() {
+        let message = escape(command.args.text("message"));
+        let speaker = match speaker(command, &conns, &players, &player_parents) {
+            Ok(speaker) => speaker,
+            Err(err) => {
+                send(&mut commands, command.conn, Err(err));
+                continue;
+            }
+        };
+        speak(
+            &mut commands,
+            command,
+            &speaker,
+            &room_conns,
+            format!("You say, {{yellow}}\"{}\"{{reset}}", message),
+            format!(
+                "{} says, {{yellow}}\"{}\"{{reset}}",
+                escape(&speaker.info.username),
+                message
+            ),
+        );
+    }
+}
+
+/// Emotes and poses are already narration, so the speaker sees the same line as everyone else.
+fn handle_emote(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<EmoteCommand>>,
+    conns: Query<&PlayerConnection>,
+    players: Query<&Player>,
+    player_parents: Query<&Parent, With<Player>>,
+    room_conns: RoomConnections,
+) {
+    for command in comms.iter() {
+        let speaker = match speaker(command, &conns, &players, &player_parents) {
+            Ok(speaker) => speaker,
+            Err(err) => {
+                send(&mut commands, command.conn, Err(err));
+                continue;
+            }
+        };
+        let message = format!(
+            "{} {}",
+            escape(&speaker.info.username),
+            escape(command.args.text("action"))
+        );
+        speak(
+            &mut commands,
+            command,
+            &speaker,
+            &room_conns,
+            message.clone(),
+            message,
+        );
+    }
+}
+
+/// Like emote, but without the space after the name, for things like `pose 's cat purrs`.
+fn handle_pose(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<PoseCommand>>,
+    conns: Query<&PlayerConnection>,
+    players: Query<&Player>,
+    player_parents: Query<&Parent, With<Player>>,
+    room_conns: RoomConnections,
+) {
+    for command in comms.iter() {
+        let speaker = match speaker(command, &conns, &players, &player_parents) {
+            Ok(speaker) => speaker,
+            Err(err) => {
+                send(&mut commands, command.conn, Err(err));
+                continue;
+            }
+        };
+        let message = format!(
+            "{}{}",
+            escape(&speaker.info.username),
+            escape(command.args.text("text"))
+        );
+        speak(
+            &mut commands,
+            command,
+            &speaker,
+            &room_conns,
+            message.clone(),
+            message,
+        );
+    }
+}
+
+fn handle_whisper(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<WhisperCommand>>,
+    players: Query<(Entity, &Player)>,
+    conns: Query<&PlayerConnection>,
+    by_player: Res<ConnectionsByPlayer>,
+    player_parents: Query<&Parent, With<Player>>,
+) {
+    for command in comms.iter() {
+        let target = command.args.entity("player");
+        // Sending to any one of their connections reaches them all
+        let target_conn = by_player.of(target).first().copied();
+        let message = escape(command.args.text("message"));
+
+        let Some((speaker, speaker_player)) = conns
+            .get(command.conn)
+            .ok()
+            .and_then(|conn| players.get(conn.object).ok())
+        else {
+            send(&mut commands, command.conn, Err(NOT_LOGGED_IN.to_owned()));
+            continue;
+        };
+        let Ok((_, target_player)) = players.get(target) else {
+            send(&mut commands, command.conn, Err(GONE.to_owned()));
+            continue;
+        };
+        let Ok(room) = player_parents.get(speaker).map(|parent| parent.get()) else {
+            send(
+                &mut commands,
+                command.conn,
+                Err("You aren't anywhere.".to_owned()),
+            );
+            continue;
+        };
+        let same_room = player_parents.get(target).is_ok_and(|p| p.get() == room);
+
+        let Some(target_conn) = target_conn.filter(|_| target != speaker && same_room) else {
+            send(
+                &mut commands,
+                command.conn,
+                Err(format!("{} isn't here.", escape(&target_player.username))),
+            );
+            continue;
+        };
+
+        send_as(
+            &mut commands,
+            target_conn,
+            MessageKind::Chat,
+            Ok(format!(
+                "{} whispers, {{yellow}}\"{}\"{{reset}}",
+                escape(&speaker_player.username),
+                message
+            )),
+        );
+        send_as(
+            &mut commands,
+            command.conn,
+            MessageKind::Chat,
+            Ok(format!(
+                "You whisper to {}, {{yellow}}\"{}\"{{reset}}",
+                escape(&target_player.username),
+                message
+            )),
+        );
+    }
+}
+
+fn handle_page(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<PageCommand>>,
+    players: Query<(Entity, &Player)>,
+    conns: Query<&PlayerConnection>,
+    by_player: Res<ConnectionsByPlayer>,
+) {
+    for command in comms.iter() {
+        let target = command.args.entity("player");
+        // Sending to any one of their connections reaches them all
+        let target_conn = by_player.of(target).first().copied();
+        let message = command.args.text("message");
+
+        let Some((speaker, speaker_player)) = conns
+            .get(command.conn)
+            .ok()
+            .and_then(|conn| players.get(conn.object).ok())
+        else {
+            send(&mut commands, command.conn, Err(NOT_LOGGED_IN.to_owned()));
+            continue;
+        };
+        let Ok((_, target_player)) = players.get(target) else {
+            send(&mut commands, command.conn, Err(GONE.to_owned()));
+            continue;
+        };
+
+        // Kept on the player rather than the connection, so it can't be sent by whoever logs in
+        // on the connection next
+        let Some(target_conn) = target_conn else {
+            commands.entity(speaker).insert(PendingMail {
+                to: target_player.username.clone(),
+                body: message.to_owned(),
+            });
+            send(
+                &mut commands,
+                command.conn,
+                Err(format!(
+                    "{} is offline. Type `mail send` to send it as mail instead.",
+                    escape(&target_player.username)
+                )),
+            );
+            continue;
+        };
+
+        send_as(
+            &mut commands,
+            target_conn,
+            MessageKind::Chat,
+            Ok(format!(
+                "{} pages: {}",
+                escape(&speaker_player.username),
+                escape(message)
+            )),
+        );
+        send_as(
+            &mut commands,
+            command.conn,
+            MessageKind::Chat,
+            Ok(format!(
+                "You page {}: {}",
+                escape(&target_player.username),
+                escape(message)
+            )),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::chat::ChatPlugin;
+    use crate::login::LoginPlugin;
+    use crate::mail::{MailPlugin, Mailbox};
+    use crate::{Object, Player, PlayerCommand};
+
+    fn chat_app() -> (
+        App,
+        Entity,
+        std::sync::mpsc::Receiver<crate::ConnectionMessageEvent>,
+        [Entity; 2],
+    ) {
+        let (mut app, conn, rx, conns) = crate::test_app::<2>();
+        app.add_plugins((LoginPlugin, ChatPlugin));
+
+        for (conn, username) in [
+            (conn, "listener"),
+            (conns[0], "speaker"),
+            (conns[1], "other"),
+        ] {
+            app.world_mut().spawn(PlayerCommand::new(
+                "register",
+                vec![username, "password"],
+                conn,
+            ));
+            crate::login::update_until_checked(&mut app);
+        }
+        rx.try_recv().unwrap();
+
+        (app, conn, rx, conns)
+    }
+
+    #[test]
+    fn say_reaches_room() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("say hello there", conns[0]));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_ok_and(|msg| msg == "speaker says, {yellow}\"hello there\"{reset}")));
+    }
+
+    #[test]
+    fn speaking_from_nowhere_fails() {
+        let (mut app, conn, rx, _conns) = chat_app();
+
+        let world = app.world_mut();
+        let listener = world
+            .query::<(Entity, &Player)>()
+            .iter(world)
+            .find(|(_, player)| player.username == "listener")
+            .unwrap()
+            .0;
+        world.entity_mut(listener).remove_parent();
+
+        for line in ["say hi", "whisper speaker | hi"] {
+            app.world_mut().spawn(PlayerCommand::parse(line, conn));
+            app.update();
+            assert!(rx
+                .try_recv()
+                .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == "You aren't anywhere.")));
+        }
+    }
+
+    #[test]
+    fn said_markup_is_escaped() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("say {red}hi", conns[0]));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_ok_and(|msg| msg == "speaker says, {yellow}\"{{red}hi\"{reset}")));
+    }
+
+    #[test]
+    fn say_does_not_reach_other_rooms() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        let world = app.world_mut();
+        let elsewhere = world.spawn(Object::default()).id();
+        let other = world
+            .query::<(Entity, &Player)>()
+            .iter(world)
+            .find(|(_, player)| player.username == "other")
+            .unwrap()
+            .0;
+        world.entity_mut(other).set_parent(elsewhere);
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("say hello", conns[1]));
+        app.update();
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn speaker_sees_first_person() {
+        let (mut app, conn, rx, _conns) = chat_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("say hello", conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_ok_and(|msg| msg == "You say, {yellow}\"hello\"{reset}")));
+    }
+
+    #[test]
+    fn emote_shorthand_works() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse(":waves", conns[0]));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "speaker waves")));
+    }
+
+    #[test]
+    fn pose_works() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("pose 's cat purrs", conns[0]));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "speaker's cat purrs")));
+    }
+
+    #[test]
+    fn whisper_reaches_target() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("whisper listener | psst", conns[0]));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_ok_and(|msg| msg == "speaker whispers, {yellow}\"psst\"{reset}")));
+    }
+
+    #[test]
+    fn whisper_does_not_reach_bystanders() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("whisper other | psst", conns[0]));
+        app.update();
+
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn page_reaches_other_rooms() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        let world = app.world_mut();
+        let elsewhere = world.spawn(Object::default()).id();
+        let speaker = world
+            .query::<(Entity, &Player)>()
+            .iter(world)
+            .find(|(_, player)| player.username == "speaker")
+            .unwrap()
+            .0;
+        world.entity_mut(speaker).set_parent(elsewhere);
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("page listener | hi", conns[0]));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "speaker pages: hi")));
+    }
+
+    #[test]
+    fn page_takes_free_text() {
+        let (mut app, _conn, rx, conns) = chat_app();
+
+        app.world_mut().spawn(PlayerCommand::parse(
+            r#"page listener | a | b "c"#,
+            conns[0],
+        ));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == r#"speaker pages: a | b "c"#)));
+    }
+
+    #[test]
+    fn page_to_offline_player_offers_mail() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, ChatPlugin, MailPlugin));
+
+        for username in ["away", "pager"] {
+            app.world_mut().spawn(PlayerCommand::new(
+                "register",
+                vec![username, "password"],
+                conn,
+            ));
+            crate::login::update_until_checked(&mut app);
+            rx.try_recv().unwrap();
+            if username == "away" {
+                app.world_mut()
+                    .spawn(PlayerCommand::new("logout", vec![], conn));
+                app.update();
+                rx.try_recv().unwrap();
+            }
+        }
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("page away | hi", conn));
+        app.update();
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail send", conn));
+        app.update();
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+
+        let world = app.world_mut();
+        let mailbox = world
+            .query::<(&Player, &Mailbox)>()
+            .iter(world)
+            .find(|(player, _)| player.username == "away")
+            .unwrap()
+            .1;
+        assert_eq!(mailbox.0.len(), 1);
+        assert_eq!(mailbox.0[0].from, "pager");
+        assert_eq!(mailbox.0[0].body, "hi");
+    }
+}
@@ -0,0 +1,374 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::Deserialize;
+
+/// Where the server listens, where it saves, and how fast it runs. Built from, in increasing order
+/// of precedence, the defaults, a TOML config file, `TEXLA_*` environment variables, and command
+/// line flags.
+#[derive(Resource, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: String,
+    pub port: u16,
+    /// The name of the room new players appear in. Uses the saved spawn room if unset.
+    pub spawn_room: Option<String>,
+    /// Ticks per second, or 0 to run as fast as possible.
+    pub tick_rate: f64,
+    pub log_level: String,
+    pub save_path: PathBuf,
+    pub autosave_secs: u64,
+    /// How long a player whose connection dropped stays in the world waiting for them to come
+    /// back, or 0 to remove them right away.
+    pub link_dead_secs: u64,
+    pub multi_login: MultiLogin,
+    /// Usernames that are always made admins, regardless of what's been granted in-game.
+    pub admins: Vec<String>,
+    /// Commands per second each connection can send once its burst is used up, or 0 for no limit.
+    pub command_rate: f64,
+    /// How many commands a connection can send back to back before it's held to the rate.
+    pub command_burst: u32,
+    /// How many commands a connection can have waiting before it's warned, and then disconnected,
+    /// for flooding.
+    pub max_queued: usize,
+    /// The longest line, in characters, a connection can send.
+    pub max_line_len: usize,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1".to_owned(),
+            port: 8080,
+            spawn_room: None,
+            tick_rate: 60.0,
+            log_level: "info".to_owned(),
+            save_path: PathBuf::from("world.ron"),
+            autosave_secs: 300,
+            link_dead_secs: 60,
+            multi_login: MultiLogin::default(),
+            admins: Vec::new(),
+            command_rate: 10.0,
+            command_burst: 20,
+            max_queued: 50,
+            max_line_len: 4096,
+        }
+    }
+}
+
+/// What happens when someone logs in as a player that's already connected.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum MultiLogin {
+    /// The new connection replaces the old one, which is kicked.
+    #[default]
+    TakeOver,
+    /// Both connections stay, and each sees everything the player does.
+    Mirror,
+    /// The new connection isn't allowed to log in.
+    Refuse,
+}
+
+impl std::str::FromStr for MultiLogin {
+    type Err = ();
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "take-over" => Ok(MultiLogin::TakeOver),
+            "mirror" => Ok(MultiLogin::Mirror),
+            "refuse" => Ok(MultiLogin::Refuse),
+            _ => Err(()),
+        }
+    }
+}
+
+const DEFAULT_CONFIG_PATH: &str = "texla.toml";
+
+/// Why the server shouldn't start with the arguments it was given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `--help` was asked for, so [`USAGE`] should be shown instead.
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Help => write!(f, "{}", USAGE),
+            ConfigError::Invalid(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl From<String> for ConfigError {
+    fn from(err: String) -> Self {
+        ConfigError::Invalid(err)
+    }
+}
+
+pub const USAGE: &str = "Usage: texla-server [options]
+
+Options:
+  --config <path>          Config file to read (default: texla.toml, if it exists)
+  --bind <address>         Address to listen on
+  --port <port>            Port to listen on
+  --spawn-room <name>      Name of the room new players appear in
+  --tick-rate <hz>         Ticks per second, or 0 for unlimited
+  --log-level <level>      One of error, warn, info, debug, trace
+  --save-path <path>       Where the world is saved
+  --autosave-secs <secs>   Seconds between autosaves
+  --link-dead-secs <secs>  Seconds disconnected players stay in the world, or 0
+  --multi-login <mode>     What a second login as the same player does: take-over, mirror
+                           or refuse
+  --admin <username>       Make a player an admin, may be repeated
+  --command-rate <hz>      Commands per second a connection can send, or 0 for unlimited
+  --command-burst <count>  Commands a connection can send at once before being limited
+  --max-queued <count>     Commands a connection can have waiting before it's disconnected
+  --max-line-len <chars>   Longest line a connection can send
+  --help                   Show this message
+
+Every option can also be set with an environment variable, like TEXLA_BIND or TEXLA_SAVE_PATH.";
+
+/// Every option, as `(flag, environment variable)`.
+const OPTIONS: [(&str, &str); 15] = [
+    ("--config", "TEXLA_CONFIG"),
+    ("--bind", "TEXLA_BIND"),
+    ("--port", "TEXLA_PORT"),
+    ("--spawn-room", "TEXLA_SPAWN_ROOM"),
+    ("--tick-rate", "TEXLA_TICK_RATE"),
+    ("--log-level", "TEXLA_LOG_LEVEL"),
+    ("--save-path", "TEXLA_SAVE_PATH"),
+    ("--autosave-secs", "TEXLA_AUTOSAVE_SECS"),
+    ("--link-dead-secs", "TEXLA_LINK_DEAD_SECS"),
+    ("--multi-login", "TEXLA_MULTI_LOGIN"),
+    ("--admin", "TEXLA_ADMINS"),
+    ("--command-rate", "TEXLA_COMMAND_RATE"),
+    ("--command-burst", "TEXLA_COMMAND_BURST"),
+    ("--max-queued", "TEXLA_MAX_QUEUED"),
+    ("--max-line-len", "TEXLA_MAX_LINE_LEN"),
+];
+
+impl ServerConfig {
+    /// Loads the config from `args` (without the program name) and the environment as seen
+    /// through `env`.
+    pub fn load(
+        args: impl IntoIterator<Item = String>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Self, ConfigError> {
+        let mut flags = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if arg == "--help" || arg == "-h" {
+                return Err(ConfigError::Help);
+            }
+            if !OPTIONS.iter().any(|(flag, _)| *flag == arg) {
+                return Err(format!("Unknown option: {}\n\n{}", arg, USAGE).into());
+            }
+            let Some(value) = args.next() else {
+                return Err(format!("Missing value for {}", arg).into());
+            };
+            flags.push((arg, value));
+        }
+
+        let flag_path = flags
+            .iter()
+            .rev()
+            .find(|(flag, _)| flag == "--config")
+            .map(|(_, value)| value.clone());
+        let mut config = match flag_path.or_else(|| env("TEXLA_CONFIG")) {
+            Some(path) => Self::from_file(&path)?,
+            None if fs::exists(DEFAULT_CONFIG_PATH).unwrap_or(false) => {
+                Self::from_file(DEFAULT_CONFIG_PATH)?
+            }
+            None => Self::default(),
+        };
+
+        for (flag, var) in OPTIONS {
+            if let Some(value) = env(var) {
+                if flag == "--admin" {
+                    config.admins.extend(
+                        value
+                            .split(",")
+                            .map(|s| s.trim().to_owned())
+                            .filter(|s| !s.is_empty()),
+                    );
+                } else {
+                    config.set(flag, value)?;
+                }
+            }
+        }
+        for (flag, value) in flags {
+            config.set(&flag, value)?;
+        }
+
+        // Rates so slow their ticks wouldn't fit in a `Duration` are as wrong as negative ones
+        let tick_rate = config.tick_rate;
+        if tick_rate.is_nan()
+            || tick_rate < 0.0
+            || (tick_rate > 0.0 && Duration::try_from_secs_f64(1.0 / tick_rate).is_err())
+        {
+            return Err(format!("Invalid tick_rate: {}", tick_rate).into());
+        }
+
+        Ok(config)
+    }
+
+    fn from_file(path: &str) -> Result<Self, String> {
+        let str = fs::read_to_string(path).map_err(|e| format!("Can't read {}: {}", path, e))?;
+        let config =
+            toml::from_str::<Self>(&str).map_err(|e| format!("Can't parse {}: {}", path, e))?;
+        if config.log_level.parse::<bevy::log::Level>().is_err() {
+            return Err(format!(
+                "Invalid log_level in {}: {}",
+                path, config.log_level
+            ));
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, flag: &str, value: String) -> Result<(), String> {
+        fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid value for {}: {}", flag, value))
+        }
+
+        match flag {
+            "--config" => {}
+            "--bind" => self.bind = value,
+            "--port" => self.port = parse(flag, &value)?,
+            "--spawn-room" => self.spawn_room = Some(value),
+            "--tick-rate" => self.tick_rate = parse(flag, &value)?,
+            "--log-level" => {
+                parse::<bevy::log::Level>(flag, &value)?;
+                self.log_level = value;
+            }
+            "--save-path" => self.save_path = PathBuf::from(value),
+            "--autosave-secs" => self.autosave_secs = parse(flag, &value)?,
+            "--link-dead-secs" => self.link_dead_secs = parse(flag, &value)?,
+            "--multi-login" => self.multi_login = parse(flag, &value)?,
+            "--admin" => self.admins.push(value),
+            "--command-rate" => self.command_rate = parse(flag, &value)?,
+            "--command-burst" => self.command_burst = parse(flag, &value)?,
+            "--max-queued" => self.max_queued = parse(flag, &value)?,
+            "--max-line-len" => self.max_line_len = parse(flag, &value)?,
+            _ => unreachable!("{} is not an option", flag),
+        }
+        Ok(())
+    }
+
+    pub fn address(&self) -> String {
+        format!("{}:{}", self.bind, self.port)
+    }
+
+    /// How long each tick lasts, from a tick rate [`ServerConfig::load`] has already checked.
+    pub fn tick_duration(&self) -> Duration {
+        if self.tick_rate > 0.0 {
+            Duration::from_secs_f64(1.0 / self.tick_rate)
+        } else {
+            Duration::ZERO
+        }
+    }
+
+    /// The log level, which [`ServerConfig::load`] has already checked. One that was set by hand
+    /// to something else falls back to info.
+    pub fn log_level(&self) -> bevy::log::Level {
+        self.log_level.parse().unwrap_or(bevy::log::Level::INFO)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::config::{ConfigError, ServerConfig};
+
+    fn load(args: &[&str], env: &[(&str, &str)]) -> Result<ServerConfig, ConfigError> {
+        let env = env
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<HashMap<_, _>>();
+        ServerConfig::load(args.iter().map(|s| s.to_string()), |key| {
+            env.get(key).cloned()
+        })
+    }
+
+    #[test]
+    fn defaults_are_used_without_options() {
+        assert_eq!(load(&[], &[]), Ok(ServerConfig::default()));
+    }
+
+    #[test]
+    fn flags_override_env() {
+        let config = load(
+            &["--port", "9000"],
+            &[("TEXLA_PORT", "9001"), ("TEXLA_BIND", "0.0.0.0")],
+        )
+        .unwrap();
+
+        assert_eq!(config.port, 9000);
+        assert_eq!(config.bind, "0.0.0.0");
+        assert_eq!(config.address(), "0.0.0.0:9000");
+    }
+
+    #[test]
+    fn env_admins_are_split() {
+        let config = load(&["--admin", "carol"], &[("TEXLA_ADMINS", "alice, bob")]).unwrap();
+
+        assert_eq!(config.admins, vec!["alice", "bob", "carol"]);
+    }
+
+    #[test]
+    fn file_is_overridden_by_flags() {
+        let path = std::env::temp_dir().join(format!("texla-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "port = 7000\nspawn_room = \"Lobby\"\ntick_rate = 20.0\n",
+        )
+        .unwrap();
+
+        let config = load(
+            &["--config", path.to_str().unwrap(), "--tick-rate", "30"],
+            &[],
+        );
+        std::fs::remove_file(&path).unwrap();
+        let config = config.unwrap();
+
+        assert_eq!(config.port, 7000);
+        assert_eq!(config.spawn_room.as_deref(), Some("Lobby"));
+        assert_eq!(config.tick_rate, 30.0);
+    }
+
+    #[test]
+    fn invalid_values_fail() {
+        assert!(load(&["--port", "lots"], &[]).is_err());
+        assert!(load(&["--port"], &[]).is_err());
+        assert!(load(&["--frobnicate", "yes"], &[]).is_err());
+        assert!(load(&["--log-level", "loud"], &[]).is_err());
+        assert!(load(&[], &[("TEXLA_LOG_LEVEL", "loud")]).is_err());
+        assert!(load(&["--tick-rate", "1e-310"], &[]).is_err());
+        assert!(load(&["--tick-rate", "-1"], &[]).is_err());
+        assert!(load(&["--tick-rate", "NaN"], &[]).is_err());
+    }
+
+    #[test]
+    fn log_level_is_parsed() {
+        assert_eq!(
+            load(&["--log-level", "debug"], &[]).map(|config| config.log_level()),
+            Ok(bevy::log::Level::DEBUG)
+        );
+    }
+
+    #[test]
+    fn help_is_not_invalid() {
+        assert_eq!(
+            load(&["--port", "1", "--help"], &[]),
+            Err(ConfigError::Help)
+        );
+        assert_eq!(load(&["-h"], &[]), Err(ConfigError::Help));
+    }
+}
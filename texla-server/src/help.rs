@@ -0,0 +1,138 @@
+use bevy::prelude::*;
+
+use crate::markup::escape;
+use crate::prelude::*;
+use crate::{CommandInfo, UsableCommands};
+
+pub struct HelpPlugin;
+
+impl Plugin for HelpPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                (preprocess_commands::<HelpCommand>,).in_set(PreprocessCommandsSet),
+                (handle_help,).in_set(HandleCommandsSet),
+            ),
+        );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((CommandHandler::<HelpCommand>::new("help")
+        .with_aliases(["?"])
+        .with_args([Arg::new("command", ArgKind::Text).optional()])
+        .with_summary("Lists the commands you can use, or explains one.")
+        .with_help(
+            "Only commands you can use right now are listed, so there may be more once you've \
+            logged in.",
+        ),));
+}
+
+#[derive(Component, Default)]
+struct HelpCommand;
+
+fn handle_help(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<HelpCommand>>,
+    usable: UsableCommands,
+) {
+    for command in comms.iter() {
+        let mut usable = usable.for_conn(command.conn);
+        usable.sort_by(|a, b| a.command.cmp(&b.command));
+
+        let name = command.args.text("command");
+        let result = if name.is_empty() {
+            Ok(list(&usable))
+        } else {
+            usable
+                .iter()
+                .find(|info| info.command == name || info.aliases.iter().any(|alias| alias == name))
+                .map(|info| explain(info))
+                .ok_or_else(|| format!("There's no command called {} you can use.", escape(name)))
+        };
+
+        send(&mut commands, command.conn, result);
+    }
+}
+
+fn list(usable: &[&CommandInfo]) -> String {
+    let width = usable
+        .iter()
+        .map(|info| info.command.len())
+        .max()
+        .unwrap_or_default();
+    let lines = usable
+        .iter()
+        .map(|info| format!("  {:width$}  {}", info.command, info.summary))
+        .map(|line| line.trim_end().to_owned())
+        .collect::<Vec<_>>();
+
+    format!(
+        "Commands:\n{}\nType `help <command>` for more about one.",
+        lines.join("\n")
+    )
+}
+
+fn explain(info: &CommandInfo) -> String {
+    let aliases = if info.aliases.is_empty() {
+        String::new()
+    } else {
+        format!("Also typed as: {}", info.aliases.join(", "))
+    };
+    [
+        info.usage(),
+        aliases,
+        info.summary.clone(),
+        info.help.clone(),
+    ]
+    .into_iter()
+    .filter(|part| !part.is_empty())
+    .collect::<Vec<_>>()
+    .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::help::HelpPlugin;
+    use crate::login::LoginPlugin;
+    use crate::roles::RolesPlugin;
+    use crate::PlayerCommand;
+
+    #[test]
+    fn help_lists_only_usable_commands() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((HelpPlugin, LoginPlugin, RolesPlugin));
+        app.update();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("help", vec![], conn));
+        app.update();
+
+        let help = rx.try_recv().unwrap().0.unwrap();
+        assert!(help.contains("register"));
+        assert!(!help.contains("logout"));
+        assert!(!help.contains("grant"));
+    }
+
+    #[test]
+    fn help_explains_a_command() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((HelpPlugin, LoginPlugin));
+        app.update();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("help", vec!["login"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_ok_and(|msg| msg.starts_with("Usage: login <username> | <password>\n"))));
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("help", vec!["logout"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+    }
+}
@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 
+use crate::markup::escape;
 use crate::prelude::*;
 
 pub struct InteractPlugin;
@@ -17,7 +18,12 @@ impl Plugin for InteractPlugin {
 }
 
 fn setup(mut commands: Commands) {
-    commands.spawn((CommandHandler::<LookCommand>::new("look"), RequiresLogin));
+    commands.spawn((
+        CommandHandler::<LookCommand>::new("look")
+            .with_aliases(["l"])
+            .with_summary("Describes the room you're in."),
+        RequiresLogin,
+    ));
 }
 
 #[derive(Component, Default)]
@@ -29,26 +35,54 @@ fn handle_look(
     conns: Query<&PlayerConnection>,
     player_parents: Query<&Parent, With<Player>>,
     looks: Query<LookBundle>,
+    exits: Query<&Exit>,
 ) {
     for command in comms.iter() {
-        let conn = conns.get(command.conn).unwrap();
-        let player_parent = player_parents.get(conn.object).unwrap();
-        let parent_look = looks.get(player_parent.get()).unwrap();
-        send(&mut commands, command.conn, Ok(look(parent_look)));
+        let Ok(conn) = conns.get(command.conn) else {
+            continue;
+        };
+        let Some(parent_look) = player_parents
+            .get(conn.object)
+            .ok()
+            .and_then(|parent| looks.get(parent.get()).ok())
+        else {
+            send(
+                &mut commands,
+                command.conn,
+                Err("You aren't anywhere.".to_owned()),
+            );
+            continue;
+        };
+        send_as(
+            &mut commands,
+            command.conn,
+            MessageKind::Room,
+            Ok(look(parent_look, &exits)),
+        );
     }
 }
 
-pub type LookBundle<'a> = (Entity, &'a Object, Option<&'a Name>);
+pub type LookBundle<'a> = (Entity, &'a Object, Option<&'a Name>, Option<&'a Children>);
+
+pub fn look((entity, obj, name, children): LookBundle, exits: &Query<&Exit>) -> String {
+    let mut lines = vec![format!(
+        "{{bold}}{}{{reset}}",
+        name.map(|n| escape(n)).unwrap_or(format!("{:?}", entity))
+    )];
 
-pub fn look((entity, obj, name): LookBundle) -> String {
-    let name = name
-        .map(|n| n.to_string())
-        .unwrap_or(format!("{:?}", entity));
-    let description = obj.properties.get("description");
+    if let Some(description) = obj.properties.get("description") {
+        lines.push(escape(description));
+    }
 
-    if let Some(description) = description {
-        format!("{}\n{}", name, description)
-    } else {
-        name
+    let exit_names = children
+        .into_iter()
+        .flatten()
+        .filter_map(|child| exits.get(*child).ok())
+        .map(|exit| escape(&exit.name))
+        .collect::<Vec<_>>();
+    if !exit_names.is_empty() {
+        lines.push(format!("Exits: {{cyan}}{}{{reset}}", exit_names.join(", ")));
     }
+
+    lines.join("\n")
 }
@@ -1,40 +1,113 @@
 use std::marker::PhantomData;
 
+use alias::Aliases;
+use args::{parse_args, Arg, ArgError, ArgKind, Args};
+use bevy::app::{ScheduleRunnerPlugin, TerminalCtrlCHandlerPlugin};
+use bevy::ecs::component::ComponentId;
+use bevy::ecs::query::QueryData;
+use bevy::ecs::system::SystemParam;
+use bevy::ecs::world::DeferredWorld;
 use bevy::log::LogPlugin;
 use bevy::prelude::*;
-use bevy::utils::HashMap;
-use login::{RequiresLogin, RequiresNoLogin};
-
+use bevy::utils::{HashMap, HashSet};
+use build::ObjectRefs;
+use config::ServerConfig;
+use login::{RequiresLogin, RequiresNoLogin, Sessions};
+use mail::Mailbox;
+use parse::{split_args, split_leading_args, ParseError};
+use queue::CommandQueue;
+use roles::{RequiresRole, Roles};
+use serde::Serialize;
+
+mod alias;
+mod args;
+mod build;
+mod chat;
+pub mod config;
+mod help;
 mod interact;
 mod login;
+mod mail;
+mod markup;
+mod movement;
+pub mod parse;
+mod persist;
+mod presence;
+mod protocol;
+mod queue;
+mod roles;
 mod utils;
 mod ws;
 
 pub mod prelude {
+    pub use crate::args::{Arg, ArgKind};
+    pub use crate::config::ServerConfig;
     pub use crate::login::{RequiresLogin, RequiresNoLogin};
+    pub use crate::movement::Exit;
+    pub use crate::roles::{RequiresRole, Role, Roles};
     pub use crate::{
-        preprocess_commands, send, CommandHandler, HandleCommandsSet, Object, Player,
-        PlayerCommand, PlayerConnection, PreprocessCommandsSet,
+        broadcast, broadcast_as, kick, preprocess_commands, send, send_as, send_direct,
+        CommandHandler, CommandRequirements, ConnectionsByPlayer, HandleCommandsSet, MessageKind,
+        Object, Player, PlayerCommand, PlayerConnection, PreprocessCommandsSet,
+        ResolveCommandsSet, RoomConnections,
     };
 }
 
-pub fn app() -> App {
+pub fn app(config: ServerConfig) -> App {
+    let mut app = headless_app();
+    app.add_plugins((
+        MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(config.tick_duration())),
+        LogPlugin {
+            level: config.log_level(),
+            ..default()
+        },
+        TerminalCtrlCHandlerPlugin,
+    ))
+    .insert_resource(config)
+    .add_plugins((ws::WsPlugin, persist::PersistPlugin));
+    app
+}
+
+/// The game without networking or saving, for driving from code like the benchmarks do. It still
+/// needs the time, a [`ServerConfig`] and a [`SpawnRoom`] before it can run.
+pub fn headless_app() -> App {
     let mut app = minimal_app();
-    app.add_plugins((MinimalPlugins, LogPlugin::default()))
-        .add_plugins((
-            login::LoginPlugin,
-            ws::WsPlugin,
-            utils::UtilsPlugin,
-            interact::InteractPlugin,
-        ))
-        .add_systems(Startup, setup);
+    app.add_plugins((
+        login::LoginPlugin,
+        utils::UtilsPlugin,
+        interact::InteractPlugin,
+        movement::MovementPlugin,
+        build::BuildPlugin,
+        roles::RolesPlugin,
+        chat::ChatPlugin,
+        mail::MailPlugin,
+        protocol::ProtocolPlugin,
+        queue::QueuePlugin,
+        presence::PresencePlugin,
+        help::HelpPlugin,
+        alias::AliasPlugin,
+    ));
     app
 }
 
 fn minimal_app() -> App {
     let mut app = App::new();
-    app.add_systems(Update, clean_up_unhandled_commands.after(HandleCommandsSet))
-        .configure_sets(Update, PreprocessCommandsSet.before(HandleCommandsSet));
+    app.init_resource::<CommandRegistry>()
+        .init_resource::<ConnectionsByPlayer>()
+        .add_systems(
+            Update,
+            (
+                route_commands.in_set(ResolveCommandsSet),
+                clean_up_unhandled_commands.after(HandleCommandsSet),
+            ),
+        )
+        .configure_sets(
+            Update,
+            (
+                ResolveCommandsSet.before(PreprocessCommandsSet),
+                PreprocessCommandsSet.before(HandleCommandsSet),
+            ),
+        );
     app
 }
 
@@ -46,6 +119,7 @@ fn test_app<const NUM_EXTRA_CONNS: usize>() -> (
     [Entity; NUM_EXTRA_CONNS],
 ) {
     let mut app = minimal_app();
+    app.add_plugins(bevy::core::TaskPoolPlugin::default());
     let world = app.world_mut();
     let (tx, rx) = std::sync::mpsc::channel();
     let conn = world
@@ -55,11 +129,14 @@ fn test_app<const NUM_EXTRA_CONNS: usize>() -> (
         })
         .id();
     let conns = [0; NUM_EXTRA_CONNS].map(|_| world.spawn((Connection,)).id());
+    let spawn_room = world.spawn((Object::default(),)).id();
+    world.insert_resource(SpawnRoom(spawn_room));
 
     (app, conn, rx, conns)
 }
 
-fn setup(mut commands: Commands) {
+/// Builds the starting room for a world that has never been saved.
+fn spawn_voidroom(world: &mut World) -> Entity {
     let mut voidroom_properties = HashMap::new();
     voidroom_properties.insert(
         "description".to_owned(),
@@ -70,32 +147,308 @@ fn setup(mut commands: Commands) {
 		afforded. You'd best find your way out of here, if one even exists."
             .to_owned(),
     );
-    let voidroom = commands
+    world
         .spawn((
             Name::new("The Voidroom"),
             Object {
                 properties: voidroom_properties,
             },
         ))
-        .id();
-
-    commands.insert_resource(SpawnRoom(voidroom));
+        .id()
 }
 
 pub fn send(commands: &mut Commands, conn: Entity, message: Result<String, String>) {
+    send_as(commands, conn, MessageKind::Text, message);
+}
+
+/// Like [`send`], but tells clients using the structured protocol what sort of message it is.
+///
+/// Messages to a logged in connection go to every connection playing the same player.
+pub fn send_as(
+    commands: &mut Commands,
+    conn: Entity,
+    kind: MessageKind,
+    message: Result<String, String>,
+) {
+    commands.queue(move |world: &mut World| {
+        let conns = match world.get::<PlayerConnection>(conn) {
+            Some(pc) => world.resource::<ConnectionsByPlayer>().of(pc.object).to_vec(),
+            None => vec![conn],
+        };
+        for conn in conns {
+            send_direct_now(world, conn, kind, message.clone());
+        }
+    });
+}
+
+/// Like [`send_as`], but only to `conn` itself, for messages about the connection rather than the
+/// player.
+pub fn send_direct(
+    commands: &mut Commands,
+    conn: Entity,
+    kind: MessageKind,
+    message: Result<String, String>,
+) {
+    commands.queue(move |world: &mut World| send_direct_now(world, conn, kind, message));
+}
+
+fn send_direct_now(
+    world: &mut World,
+    conn: Entity,
+    kind: MessageKind,
+    message: Result<String, String>,
+) {
+    // The connection may have closed since the message was queued
+    if world.get_entity(conn).is_ok() {
+        world.trigger_targets(ConnectionMessageEvent(message, kind), conn);
+    }
+}
+
+/// Logs a connection out and closes it, telling it why first.
+pub fn kick(commands: &mut Commands, conn: Entity, reason: String) {
+    send_direct(commands, conn, MessageKind::Disconnect, Err(reason.clone()));
     commands
         .entity(conn)
-        .trigger(ConnectionMessageEvent(message));
+        .remove::<PlayerConnection>()
+        .trigger(CloseConnectionEvent(reason));
+}
+
+pub fn broadcast(
+    commands: &mut Commands,
+    conns: impl IntoIterator<Item = Entity>,
+    message: Result<String, String>,
+) {
+    broadcast_as(commands, conns, MessageKind::Text, message);
+}
+
+pub fn broadcast_as(
+    commands: &mut Commands,
+    conns: impl IntoIterator<Item = Entity>,
+    kind: MessageKind,
+    message: Result<String, String>,
+) {
+    for conn in conns {
+        send_as(commands, conn, kind, message.clone());
+    }
+}
+
+/// Finds the connections of the players standing in a room, for use with [`broadcast`].
+#[derive(SystemParam)]
+pub struct RoomConnections<'w, 's> {
+    conns: Query<'w, 's, (Entity, &'static PlayerConnection)>,
+    parents: Query<'w, 's, &'static Parent, With<Player>>,
+}
+
+impl RoomConnections<'_, '_> {
+    /// A `(connection, player)` pair for every player in `room`. Players with several connections
+    /// are only listed once, since sending to one of their connections reaches them all.
+    pub fn in_room(&self, room: Entity) -> impl Iterator<Item = (Entity, Entity)> + '_ {
+        let mut seen = HashSet::new();
+        self.conns
+            .iter()
+            .filter(move |(_, pc)| self.parents.get(pc.object).is_ok_and(|p| p.get() == room))
+            .filter(move |(_, pc)| seen.insert(pc.object))
+            .map(|(conn, pc)| (conn, pc.object))
+    }
+}
+
+/// Every command handler, for finding the ones a connection can use without knowing their types.
+#[derive(SystemParam)]
+pub struct UsableCommands<'w, 's> {
+    handlers: Query<'w, 's, (&'static CommandInfo, CommandRequirements)>,
+    conns: Query<'w, 's, Option<&'static PlayerConnection>, With<Connection>>,
+    roles: Query<'w, 's, &'static Roles>,
+}
+
+impl UsableCommands<'_, '_> {
+    /// The commands `conn` is allowed to use right now.
+    pub fn for_conn(&self, conn: Entity) -> Vec<&CommandInfo> {
+        let Ok(logged_in) = self.conns.get(conn) else {
+            return Vec::new();
+        };
+        self.handlers
+            .iter()
+            .filter(|(_, requirements)| requirements.check(logged_in, &self.roles).is_ok())
+            .map(|(info, _)| info)
+            .collect()
+    }
+}
+
+/// Every command name and alias, and the handler each one goes to.
+#[derive(Resource, Debug, Default)]
+pub struct CommandRegistry(HashMap<String, Route>);
+
+impl CommandRegistry {
+    /// Where a command typed as `typed` goes, by its name, an alias, or an abbreviation of one of
+    /// the `usable` commands. Those are only listed if `typed` isn't a name or alias.
+    fn resolve<'a>(
+        &self,
+        usable: impl FnOnce() -> Vec<&'a CommandInfo>,
+        typed: &str,
+    ) -> Option<&Route> {
+        self.0
+            .get(typed)
+            .or_else(|| complete_command(&usable(), typed).and_then(|name| self.0.get(name)))
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Route {
+    command: String,
+    handler: Entity,
+    route: fn(&mut EntityCommands, Entity),
 }
 
-fn clean_up_unhandled_commands(mut commands: Commands, comms: Query<(Entity, &PlayerCommand)>) {
+/// Marks a command as one for the handler of type `T` on `handler`, so that only that handler's
+/// [`preprocess_commands`] looks at it.
+#[derive(Component, Debug)]
+pub struct Routed<T: Component> {
+    handler: Entity,
+    _phantom: PhantomData<T>,
+}
+
+fn route<T: Component>(commands: &mut EntityCommands, handler: Entity) {
+    commands.insert(Routed::<T> {
+        handler,
+        _phantom: PhantomData,
+    });
+}
+
+/// Looks up the handler for each new command by the name, alias or abbreviation typed, and sends
+/// the command its way under the command's full name.
+fn route_commands(
+    mut commands: Commands,
+    mut comms: Query<(Entity, &mut PlayerCommand)>,
+    registry: Res<CommandRegistry>,
+    usable: UsableCommands,
+) {
+    for (entity, mut command) in comms.iter_mut() {
+        if matches!(command.state, CommandState::Handled) {
+            continue;
+        }
+
+        let Some(mut route) =
+            registry.resolve(|| usable.for_conn(command.conn), &command.inner.command)
+        else {
+            continue;
+        };
+        if let Some((sub_route, inner)) = command
+            .inner
+            .subcommand(&route.command)
+            .and_then(|inner| Some((registry.0.get(&inner.command)?, inner)))
+        {
+            route = sub_route;
+            command.inner = inner;
+        }
+
+        command.inner.command.clone_from(&route.command);
+        (route.route)(&mut commands.entity(entity), route.handler);
+    }
+}
+
+/// The only command `typed` is the start of, if there's just one. Subcommands are left to the
+/// command they belong to.
+fn complete_command<'a>(usable: &[&'a CommandInfo], typed: &str) -> Option<&'a str> {
+    if typed.is_empty() {
+        return None;
+    }
+
+    match usable
+        .iter()
+        .filter(|info| !info.is_subcommand() && info.command.starts_with(typed))
+        .collect::<Vec<_>>()[..]
+    {
+        [info] => Some(&info.command),
+        _ => None,
+    }
+}
+
+/// The commands someone who typed `typed` most likely meant, closest first.
+fn suggest_commands<'a>(usable: &[&'a CommandInfo], typed: &str) -> Vec<&'a str> {
+    let names = usable
+        .iter()
+        .filter(|info| !info.is_subcommand())
+        .flat_map(|info| std::iter::once(&info.command).chain(&info.aliases))
+        .map(String::as_str);
+
+    // Too short to be unique, so anything it starts is a good guess
+    let mut prefixed = names
+        .clone()
+        .filter(|name| !typed.is_empty() && name.starts_with(typed))
+        .collect::<Vec<_>>();
+    if !prefixed.is_empty() {
+        prefixed.sort();
+        prefixed.dedup();
+        return prefixed;
+    }
+
+    let typed_len = typed.chars().count();
+    let mut close = names
+        .map(|name| (edit_distance(typed, name), name))
+        .filter(|(distance, _)| *distance <= 2 && *distance < typed_len)
+        .collect::<Vec<_>>();
+    close.sort();
+    close.dedup();
+    let best = close.first().map(|(distance, _)| *distance);
+    close
+        .into_iter()
+        .take_while(|(distance, _)| Some(*distance) == best)
+        .take(3)
+        .map(|(_, name)| name)
+        .collect()
+}
+
+/// How many characters have to be inserted, removed, or replaced to turn `a` into `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut row = (0..=b.len()).collect::<Vec<_>>();
+    for (i, a) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, b) in b.iter().enumerate() {
+            let replaced = diagonal + usize::from(a != *b);
+            diagonal = row[j + 1];
+            row[j + 1] = replaced.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Writes a list out like `a, b or c`.
+fn or_list(items: &[&str]) -> String {
+    match items {
+        [] => String::new(),
+        [item] => item.to_string(),
+        [rest @ .., last] => format!("{} or {}", rest.join(", "), last),
+    }
+}
+
+fn clean_up_unhandled_commands(
+    mut commands: Commands,
+    comms: Query<(Entity, &PlayerCommand)>,
+    conns: Query<(), With<Connection>>,
+    usable: UsableCommands,
+) {
     for (entity, command) in comms.iter() {
         match &command.state {
+            // Nobody's left to tell if the connection closed
+            CommandState::NotHandled if !conns.contains(command.conn) => {}
             CommandState::NotHandled => {
+                let suggestions =
+                    suggest_commands(&usable.for_conn(command.conn), &command.inner.command);
+                let hint = if suggestions.is_empty() {
+                    "Type `help` for a list of commands.".to_owned()
+                } else {
+                    format!("Did you mean {}?", or_list(&suggestions))
+                };
                 send(
                     &mut commands,
                     command.conn,
-                    Err(format!("Unknown command: {}", command.inner.command)),
+                    Err(format!(
+                        "Unknown command: {}. {}",
+                        markup::escape(&command.inner.command),
+                        hint
+                    )),
                 );
             }
             CommandState::Handled => {}
@@ -106,41 +459,47 @@ fn clean_up_unhandled_commands(mut commands: Commands, comms: Query<(Entity, &Pl
 
 pub fn preprocess_commands<T: Component + Default>(
     mut commands: Commands,
-    handlers: Query<(
-        &CommandHandler<T>,
-        Option<&RequiresLogin>,
-        Option<&RequiresNoLogin>,
-    )>,
-    mut comms: Query<(Entity, &mut PlayerCommand)>,
+    handlers: Query<(&CommandHandler<T>, CommandRequirements)>,
+    mut comms: Query<(Entity, &mut PlayerCommand, &Routed<T>)>,
     conns: Query<Option<&PlayerConnection>, With<Connection>>,
+    roles: Query<&Roles>,
+    players: Query<(Entity, &Player)>,
+    refs: ObjectRefs,
 ) {
-    for (entity, mut command) in comms.iter_mut() {
-        let Some((_, req_login, req_no_login)) = handlers
-            .iter()
-            .find(|(h, _, _)| h.command == command.inner.command)
-        else {
+    for (entity, mut command, routed) in comms.iter_mut() {
+        let Ok((handler, requirements)) = handlers.get(routed.handler) else {
+            continue;
+        };
+        let Ok(logged_in) = conns.get(command.conn) else {
             continue;
         };
-        let logged_in = conns.get(command.conn).unwrap();
 
         command.state = CommandState::Handled;
 
-        if req_login.is_some() && logged_in.is_none() {
-            send(
-                &mut commands,
-                command.conn,
-                Err("You must be logged in to do that.".to_owned()),
-            );
+        if let Err(err) = requirements.check(logged_in, &roles) {
+            send(&mut commands, command.conn, Err(err));
             continue;
         }
 
-        if req_no_login.is_some() && logged_in.is_some() {
-            send(
-                &mut commands,
-                command.conn,
-                Err("You must not be logged in to do that.".to_owned()),
-            );
-            continue;
+        let find = |kind, name: &str| match kind {
+            ArgKind::Player => players
+                .iter()
+                .find(|(_, player)| player.username == name)
+                .map(|(player, _)| player)
+                .ok_or_else(|| format!("No player named {}.", markup::escape(name))),
+            _ if logged_in.is_none() => Err("You must be logged in to do that.".to_owned()),
+            _ => refs.resolve(command.conn, name),
+        };
+        match parse_args(&handler.info.args, &command.inner, find) {
+            Ok(args) => command.args = args,
+            Err(ArgError::Usage) => {
+                send(&mut commands, command.conn, Err(handler.info.usage()));
+                continue;
+            }
+            Err(ArgError::Invalid(err)) => {
+                send(&mut commands, command.conn, Err(err));
+                continue;
+            }
         }
 
         commands.entity(entity).insert(T::default());
@@ -148,20 +507,74 @@ pub fn preprocess_commands<T: Component + Default>(
 }
 
 #[derive(Component, Debug, Default)]
+#[require(CommandQueue)]
 pub struct Connection;
 
 #[derive(Event, Debug, Clone)]
-pub struct ConnectionMessageEvent(pub Result<String, String>);
+pub struct ConnectionMessageEvent(pub Result<String, String>, pub MessageKind);
+
+/// Asks for a connection to be closed from the server's end, saying why.
+#[derive(Event, Debug, Clone)]
+pub struct CloseConnectionEvent(pub String);
+
+/// What a message is about, so that clients can present each sort differently.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageKind {
+    #[default]
+    Text,
+    Room,
+    Chat,
+    Prompt,
+    Protocol,
+    /// A token for resuming the session after a disconnect.
+    Session,
+    /// Why the server is about to close the connection. Clients shouldn't reconnect after one.
+    Disconnect,
+}
 
 #[derive(Component, Debug)]
+#[component(on_insert = index_connection, on_replace = unindex_connection)]
 pub struct PlayerConnection {
     pub object: Entity,
 }
 
+/// The connections playing each player, kept up to date as [`PlayerConnection`]s come and go so
+/// messages can find them without looking through every connection.
+#[derive(Resource, Debug, Default)]
+pub struct ConnectionsByPlayer(HashMap<Entity, Vec<Entity>>);
+
+impl ConnectionsByPlayer {
+    pub fn of(&self, player: Entity) -> &[Entity] {
+        self.0.get(&player).map_or(&[], Vec::as_slice)
+    }
+}
+
+fn index_connection(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
+    let player = world.get::<PlayerConnection>(entity).unwrap().object;
+    if let Some(mut index) = world.get_resource_mut::<ConnectionsByPlayer>() {
+        index.0.entry(player).or_default().push(entity);
+    }
+}
+
+fn unindex_connection(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
+    let player = world.get::<PlayerConnection>(entity).unwrap().object;
+    if let Some(mut index) = world.get_resource_mut::<ConnectionsByPlayer>() {
+        if let Some(conns) = index.0.get_mut(&player) {
+            conns.retain(|conn| *conn != entity);
+            if conns.is_empty() {
+                index.0.remove(&player);
+            }
+        }
+    }
+}
+
 #[derive(Component, Debug)]
+#[require(Roles, Mailbox, Sessions, Aliases)]
 pub struct Player {
     pub username: String,
-    pub password: String,
+    /// An argon2 PHC string, or a legacy plaintext password awaiting migration.
+    pub password_hash: String,
 }
 
 #[derive(Component, Debug, Default)]
@@ -175,6 +588,8 @@ pub struct SpawnRoom(pub Entity);
 #[derive(Component, Debug)]
 pub struct PlayerCommand {
     inner: CommandInner,
+    /// The arguments, checked against the handler's schema.
+    args: Args,
     state: CommandState,
     conn: Entity,
 }
@@ -184,16 +599,20 @@ impl PlayerCommand {
         Self {
             inner: CommandInner {
                 command: command.to_owned(),
-                args: args.into_iter().map(|s| s.to_owned()).collect(),
+                text: args.join(" | "),
+                args: Some(args.into_iter().map(|s| s.to_owned()).collect()),
             },
+            args: Args::default(),
             state: CommandState::NotHandled,
             conn,
         }
     }
 
-    pub fn from_str(str: String, conn: Entity) -> Self {
+    /// Parses a line typed by a player.
+    pub fn parse(str: &str, conn: Entity) -> Self {
         Self {
-            inner: CommandInner::from(str),
+            inner: CommandInner::parse(str),
+            args: Args::default(),
             state: CommandState::NotHandled,
             conn,
         }
@@ -209,20 +628,96 @@ pub enum CommandState {
 #[derive(Debug)]
 pub struct CommandInner {
     pub command: String,
-    pub args: Vec<String>,
+    /// Everything after the command, unsplit, for commands that take free text.
+    pub text: String,
+    /// The arguments of a command made in code. Typed ones are split from `text` once their
+    /// handler is known, since free text isn't split at all.
+    pub args: Option<Vec<String>>,
 }
 
-impl From<String> for CommandInner {
-    fn from(str: String) -> Self {
-        let (command, args) = str.split_once(" ").unwrap_or((&str, ""));
-        let args = args.split("|").map(|s| s.trim().to_owned()).collect();
+/// Command aliases that can be typed directly against their text, like `:waves`.
+const PREFIX_COMMANDS: [&str; 1] = [":"];
+
+impl CommandInner {
+    /// Trailing whitespace is left on the arguments, since it may have been escaped, and
+    /// splitting them drops it otherwise.
+    pub fn parse(str: &str) -> Self {
+        let str = str.trim_start();
+        let (command, args) = PREFIX_COMMANDS
+            .iter()
+            .find_map(|prefix| str.strip_prefix(prefix).map(|args| (*prefix, args)))
+            .or_else(|| str.split_once(char::is_whitespace))
+            .unwrap_or((str, ""));
         Self {
             command: command.trim().to_owned(),
-            args,
+            text: args.trim_start().to_owned(),
+            args: None,
+        }
+    }
+
+    /// The arguments, split on `|`.
+    pub fn split(&self) -> Result<Vec<String>, ParseError> {
+        match &self.args {
+            Some(args) => Ok(args.clone()),
+            None => split_args(&self.text),
+        }
+    }
+
+    /// The command as a subcommand of `command`, like `mail read`, with the word that names it
+    /// taken off the arguments.
+    fn subcommand(&self, command: &str) -> Option<Self> {
+        let (name, inner) = match &self.args {
+            Some(args) => {
+                let (name, args) = args.split_first()?;
+                (
+                    name.as_str(),
+                    Self {
+                        command: String::new(),
+                        text: args.join(" | "),
+                        args: Some(args.to_vec()),
+                    },
+                )
+            }
+            None => {
+                let (name, text) = self
+                    .text
+                    .split_once(char::is_whitespace)
+                    .unwrap_or((&self.text, ""));
+                (
+                    name,
+                    Self {
+                        command: String::new(),
+                        text: text.trim_start().to_owned(),
+                        args: None,
+                    },
+                )
+            }
+        };
+
+        (!name.is_empty()).then(|| Self {
+            command: format!("{} {}", command, name),
+            ..inner
+        })
+    }
+
+    /// The first `count` arguments, split on `|`, and the free text after them.
+    pub fn split_leading(&self, count: usize) -> Result<(Vec<String>, String), ParseError> {
+        match &self.args {
+            Some(args) => {
+                let count = count.min(args.len());
+                Ok((args[..count].to_vec(), args[count..].join(" | ")))
+            }
+            None => {
+                split_leading_args(&self.text, count).map(|(args, rest)| (args, rest.to_owned()))
+            }
         }
     }
 }
 
+/// Where commands are renamed to the handlers they're meant for, before any handler looks at them.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ResolveCommandsSet;
+
 #[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct PreprocessCommandsSet;
 
@@ -230,18 +725,143 @@ pub struct PreprocessCommandsSet;
 pub struct HandleCommandsSet;
 
 #[derive(Component, Debug)]
-pub struct CommandHandler<T> {
-    command: String,
+#[component(on_add = register_handler::<T>, on_remove = unregister_handler)]
+pub struct CommandHandler<T: Component> {
+    info: CommandInfo,
     _phantom: PhantomData<T>,
 }
 
-impl<T> CommandHandler<T> {
+impl<T: Component> CommandHandler<T> {
+    /// A handler for `command`, which can be a subcommand of another handler's command, like
+    /// `mail read`.
     pub fn new(command: &str) -> Self {
         Self {
-            command: command.to_owned(),
+            info: CommandInfo {
+                command: command.to_owned(),
+                aliases: Vec::new(),
+                args: Vec::new(),
+                summary: String::new(),
+                help: String::new(),
+            },
             _phantom: PhantomData,
         }
     }
+
+    /// Sets other names the command can be typed as, like `l` for `look`.
+    pub fn with_aliases(mut self, aliases: impl IntoIterator<Item = &'static str>) -> Self {
+        self.info.aliases = aliases.into_iter().map(|alias| alias.to_owned()).collect();
+        self
+    }
+
+    /// Sets the arguments the command takes. Commands that don't set any take none.
+    pub fn with_args(mut self, args: impl IntoIterator<Item = Arg>) -> Self {
+        self.info.args = args.into_iter().collect();
+        self
+    }
+
+    /// Sets the one line description `help` lists the command with.
+    pub fn with_summary(mut self, summary: &str) -> Self {
+        self.info.summary = summary.to_owned();
+        self
+    }
+
+    /// Sets the longer explanation shown by `help <command>`.
+    pub fn with_help(mut self, help: &str) -> Self {
+        self.info.help = help.to_owned();
+        self
+    }
+}
+
+/// Adds a handler's names to the [`CommandRegistry`], and copies its [`CommandInfo`] onto its
+/// entity, where it can be found without knowing the handler's type.
+///
+/// Panics if one of the names is already taken, since only one of the handlers could be reached.
+fn register_handler<T: Component>(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
+    let info = world.get::<CommandHandler<T>>(entity).unwrap().info.clone();
+    if let Some(mut registry) = world.get_resource_mut::<CommandRegistry>() {
+        for name in std::iter::once(&info.command).chain(&info.aliases) {
+            if let Some(taken) = registry.0.get(name) {
+                panic!(
+                    "Can't register {} for {}, it's already {}",
+                    name, info.command, taken.command
+                );
+            }
+            registry.0.insert(
+                name.clone(),
+                Route {
+                    command: info.command.clone(),
+                    handler: entity,
+                    route: route::<T>,
+                },
+            );
+        }
+    }
+    world.commands().entity(entity).insert(info);
+}
+
+fn unregister_handler(mut world: DeferredWorld, entity: Entity, _: ComponentId) {
+    if let Some(mut registry) = world.get_resource_mut::<CommandRegistry>() {
+        registry.0.retain(|_, route| route.handler != entity);
+    }
+}
+
+/// What a command is called and how it's used, for every handler whatever its type.
+#[derive(Component, Debug, Clone)]
+pub struct CommandInfo {
+    pub command: String,
+    pub aliases: Vec<String>,
+    pub args: Vec<Arg>,
+    pub summary: String,
+    pub help: String,
+}
+
+impl CommandInfo {
+    /// Whether this is a subcommand, like `mail read`, which is only reached through its command.
+    pub fn is_subcommand(&self) -> bool {
+        self.command.contains(' ')
+    }
+
+    pub fn usage(&self) -> String {
+        format!("Usage: {} {}", self.command, args::usage(&self.args))
+            .trim_end()
+            .to_owned()
+    }
+}
+
+/// Who's allowed to use a command handler.
+#[derive(QueryData)]
+pub struct CommandRequirements {
+    login: Has<RequiresLogin>,
+    no_login: Has<RequiresNoLogin>,
+    role: Option<&'static RequiresRole>,
+}
+
+impl CommandRequirementsItem<'_> {
+    /// Why a connection logged in as `logged_in` can't use the command, if it can't.
+    pub fn check(
+        &self,
+        logged_in: Option<&PlayerConnection>,
+        roles: &Query<&Roles>,
+    ) -> Result<(), String> {
+        if self.login && logged_in.is_none() {
+            return Err("You must be logged in to do that.".to_owned());
+        }
+
+        if self.no_login && logged_in.is_some() {
+            return Err("You must not be logged in to do that.".to_owned());
+        }
+
+        if let Some(req_role) = self.role {
+            if !logged_in
+                .and_then(|conn| roles.get(conn.object).ok())
+                .is_some_and(|roles| roles.has(req_role.0))
+            {
+                return Err(req_role.error());
+            }
+        }
+
+        Ok(())
+    }
 }
 
 #[derive(Event, Debug)]
@@ -252,3 +872,87 @@ impl<T> Default for CommandTrigger<T> {
         Self(PhantomData)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::{
+        complete_command, edit_distance, suggest_commands, CommandHandler, CommandInfo,
+        ConnectionsByPlayer, PlayerConnection,
+    };
+
+    fn info(command: &str, aliases: &[&str]) -> CommandInfo {
+        CommandInfo {
+            command: command.to_owned(),
+            aliases: aliases.iter().map(|alias| alias.to_string()).collect(),
+            args: Vec::new(),
+            summary: String::new(),
+            help: String::new(),
+        }
+    }
+
+    #[test]
+    fn commands_complete_from_a_unique_prefix() {
+        let infos = [
+            info("look", &["l"]),
+            info("login", &[]),
+            info("logout", &[]),
+        ];
+        let usable = infos.iter().collect::<Vec<_>>();
+
+        assert_eq!(complete_command(&usable, "loo"), Some("look"));
+        assert_eq!(complete_command(&usable, "logi"), Some("login"));
+        assert_eq!(complete_command(&usable, "lo"), None);
+        assert_eq!(complete_command(&usable, "x"), None);
+    }
+
+    #[test]
+    fn unknown_commands_get_suggestions() {
+        let infos = [
+            info("look", &["l"]),
+            info("login", &[]),
+            info("logout", &[]),
+        ];
+        let usable = infos.iter().collect::<Vec<_>>();
+
+        assert_eq!(suggest_commands(&usable, "lok"), vec!["look"]);
+        assert_eq!(suggest_commands(&usable, "log"), vec!["login", "logout"]);
+        assert!(suggest_commands(&usable, "xyzzy").is_empty());
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn connections_are_indexed_by_player() {
+        let (mut app, conn, _rx, conns) = crate::test_app::<1>();
+        let world = app.world_mut();
+        let player = world.spawn_empty().id();
+
+        for conn in [conn, conns[0]] {
+            world
+                .entity_mut(conn)
+                .insert(PlayerConnection { object: player });
+        }
+        assert_eq!(
+            world.resource::<ConnectionsByPlayer>().of(player),
+            [conn, conns[0]]
+        );
+
+        world.entity_mut(conn).remove::<PlayerConnection>();
+        world.despawn(conns[0]);
+        assert!(world.resource::<ConnectionsByPlayer>().of(player).is_empty());
+    }
+
+    #[derive(Component, Default)]
+    struct TestCommand;
+
+    #[test]
+    #[should_panic(expected = "Can't register l for list, it's already look")]
+    fn handlers_cant_share_names() {
+        let (mut app, _conn, _rx, _conns) = crate::test_app::<0>();
+        let world = app.world_mut();
+
+        world.spawn(CommandHandler::<TestCommand>::new("look").with_aliases(["l"]));
+        world.spawn(CommandHandler::<TestCommand>::new("list").with_aliases(["l"]));
+    }
+}
@@ -1,41 +1,87 @@
+use std::sync::LazyLock;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use argon2::password_hash::{
+    self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString,
+};
+use argon2::Argon2;
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
+use bevy::tasks::{block_on, futures_lite::future, AsyncComputeTaskPool, Task};
+use sha2::{Digest, Sha256};
 
+use crate::config::{MultiLogin, ServerConfig};
 use crate::interact::{look, LookBundle};
 use crate::prelude::*;
-use crate::SpawnRoom;
+use crate::protocol::WireFormat;
+use crate::{CommandInner, Connection, SpawnRoom};
 
 pub struct LoginPlugin;
 
 impl Plugin for LoginPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup).add_systems(
-            Update,
-            (
+        app.init_resource::<ServerConfig>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
                 (
-                    preprocess_commands::<LoginCommand>,
-                    preprocess_commands::<RegisterCommand>,
-                    preprocess_commands::<LogoutCommand>,
-                )
-                    .in_set(PreprocessCommandsSet),
-                (handle_login, handle_register, handle_logout).in_set(HandleCommandsSet),
-            ),
-        );
+                    (
+                        preprocess_commands::<LoginCommand>,
+                        preprocess_commands::<RegisterCommand>,
+                        preprocess_commands::<LogoutCommand>,
+                        preprocess_commands::<ResumeCommand>,
+                    )
+                        .in_set(PreprocessCommandsSet),
+                    (
+                        handle_login,
+                        handle_register,
+                        handle_logout,
+                        handle_resume,
+                        finish_logins,
+                        finish_registrations,
+                    )
+                        .in_set(HandleCommandsSet),
+                    issue_session_tokens.after(HandleCommandsSet),
+                ),
+            );
     }
 }
 
 fn setup(mut commands: Commands) {
+    // Hashed up front so a broken hasher is found at startup rather than on the first login
+    LazyLock::force(&DUMMY_PASSWORD_HASH);
+
     commands.spawn((
-        CommandHandler::<LoginCommand>::new("login"),
+        CommandHandler::<LoginCommand>::new("login")
+            .with_args([
+                Arg::new("username", ArgKind::Text),
+                Arg::new("password", ArgKind::Text),
+            ])
+            .with_summary("Logs in as an existing player.")
+            .with_help("Quote a password with a pipe in it, like `login alice | \"pass|word\"`."),
         RequiresNoLogin,
     ));
     commands.spawn((
-        CommandHandler::<RegisterCommand>::new("register"),
+        CommandHandler::<RegisterCommand>::new("register")
+            .with_args([
+                Arg::new("username", ArgKind::Text),
+                Arg::new("password", ArgKind::Text),
+            ])
+            .with_summary("Creates a new player and logs in as them."),
         RequiresNoLogin,
     ));
     commands.spawn((
-        CommandHandler::<LogoutCommand>::new("logout"),
+        CommandHandler::<LogoutCommand>::new("logout")
+            .with_summary("Logs out, leaving you connected."),
         RequiresLogin,
     ));
+    commands.spawn((
+        CommandHandler::<ResumeCommand>::new("resume")
+            .with_args([Arg::new("token", ArgKind::Text)])
+            .with_summary("Picks up where you left off after a disconnect.")
+            .with_help("Clients using the JSON protocol are given tokens and send this for you."),
+        RequiresNoLogin,
+    ));
 }
 
 #[derive(Component, Default)]
@@ -47,43 +93,165 @@ struct RegisterCommand;
 #[derive(Component, Default)]
 struct LogoutCommand;
 
+#[derive(Component, Default)]
+struct ResumeCommand;
+
+/// How many sessions a player can have open to resume at once. Logging in again past this drops
+/// the oldest.
+const MAX_SESSIONS: usize = 5;
+
+/// Hashes of the tokens that can be traded for a login with `resume`, oldest first. Only the
+/// hashes are kept, so a copy of the save file can't be used to resume as anyone.
+#[derive(Component, Debug, Default, Clone)]
+pub struct Sessions(pub Vec<String>);
+
+/// The hash of the session token a connection was issued, so it can be revoked on logout.
+#[derive(Component, Debug)]
+struct Session(String);
+
+/// A login waiting on its password to be checked, which is too slow to do in the middle of a tick.
+#[derive(Component)]
+struct PendingLogin {
+    conn: Entity,
+    /// The player logging in, or `None` if there's no player by that name.
+    player: Option<Entity>,
+    task: Task<PasswordCheck>,
+}
+
+/// A registration waiting on its password to be hashed.
+#[derive(Component)]
+struct PendingRegistration {
+    conn: Entity,
+    username: String,
+    roles: Roles,
+    task: Task<password_hash::Result<String>>,
+}
+
+enum PasswordCheck {
+    Wrong,
+    Right,
+    /// Right, for a legacy plaintext password, which has been hashed to replace it.
+    Migrated(String),
+}
+
+/// Which connections are waiting on a login or registration, so they can't start another.
+#[derive(SystemParam)]
+struct Pending<'w, 's> {
+    logins: Query<'w, 's, &'static PendingLogin>,
+    registrations: Query<'w, 's, &'static PendingRegistration>,
+}
+
+impl Pending<'_, '_> {
+    fn contains(&self, conn: Entity) -> bool {
+        self.logins.iter().any(|login| login.conn == conn)
+            || self.registrations.iter().any(|reg| reg.conn == conn)
+    }
+}
+
 fn handle_login(
     mut commands: Commands,
     comms: Query<&PlayerCommand, With<LoginCommand>>,
     players: Query<(Entity, &Player)>,
+    pending: Pending,
 ) {
+    // Logins aren't pending until the end of the tick, so ones started this tick are tracked here
+    let mut started = Vec::new();
     for command in comms.iter() {
-        if command.inner.args.len() < 2 {
+        if pending.contains(command.conn) || started.contains(&command.conn) {
+            send(&mut commands, command.conn, Err(STILL_PENDING.to_owned()));
+            continue;
+        }
+        started.push(command.conn);
+
+        let username = command.args.text("username");
+        let password = command.args.text("password").to_owned();
+
+        // Unknown usernames still check a password, so they take as long as known ones
+        let found = players
+            .iter()
+            .find(|(_, player)| player.username == username);
+        let password_hash = found.map(|(_, player)| player.password_hash.clone());
+        let task = AsyncComputeTaskPool::get().spawn(async move {
+            check_password(
+                password_hash.as_deref().unwrap_or(DUMMY_PASSWORD_HASH.as_str()),
+                &password,
+            )
+        });
+
+        commands.spawn(PendingLogin {
+            conn: command.conn,
+            player: found.map(|(player, _)| player),
+            task,
+        });
+    }
+}
+
+/// Logs in the connections whose passwords have been checked.
+fn finish_logins(
+    mut commands: Commands,
+    mut logins: Query<(Entity, &mut PendingLogin)>,
+    mut players: Query<&mut Player>,
+    conns: Query<(), With<PlayerConnection>>,
+    by_player: Res<ConnectionsByPlayer>,
+    open: Query<(), With<Connection>>,
+    config: Res<ServerConfig>,
+) {
+    // Connections aren't attached until the end of the tick, so ones attached this tick are
+    // tracked here as `(connection, player)`
+    let mut attached = Vec::new();
+    for (entity, mut login) in logins.iter_mut() {
+        let Some(check) = block_on(future::poll_once(&mut login.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+
+        // Nobody's left to tell if the connection closed
+        if !open.contains(login.conn) {
+            continue;
+        }
+        // It may have resumed a session while the password was checked
+        if conns.contains(login.conn) || attached.iter().any(|(conn, _)| *conn == login.conn) {
             send(
                 &mut commands,
-                command.conn,
-                Err("Usage: login <username> | <password>".to_owned()),
+                login.conn,
+                Err("You must not be logged in to do that.".to_owned()),
             );
             continue;
         }
 
-        let username = command.inner.args[0].clone();
-        let password = command.inner.args[1].clone();
-
-        let Some((player_entity, _player)) = players
-            .iter()
-            .find(|(_, player)| player.username == username && player.password == password)
-        else {
+        let found = login
+            .player
+            .filter(|_| !matches!(check, PasswordCheck::Wrong))
+            .and_then(|player| Some((player, players.get_mut(player).ok()?)));
+        let Some((player_entity, mut player)) = found else {
             send(
                 &mut commands,
-                command.conn,
+                login.conn,
                 Err("Invalid username or password.".to_owned()),
             );
             continue;
         };
 
-        commands.entity(command.conn).insert(PlayerConnection {
-            object: player_entity,
-        });
+        if let Err(err) = attach(
+            &mut commands,
+            &by_player,
+            &mut attached,
+            config.multi_login,
+            login.conn,
+            player_entity,
+        ) {
+            send(&mut commands, login.conn, Err(err));
+            continue;
+        }
+
+        if let PasswordCheck::Migrated(password_hash) = check {
+            info!("Migrating plaintext password for {}", player.username);
+            player.password_hash = password_hash;
+        }
 
         send(
             &mut commands,
-            command.conn,
+            login.conn,
             Ok("Successfully logged in.".to_owned()),
         );
     }
@@ -92,26 +260,28 @@ fn handle_login(
 fn handle_register(
     mut commands: Commands,
     comms: Query<&PlayerCommand, With<RegisterCommand>>,
-    players: Query<(Entity, &Player)>,
-    spawn_room: Res<SpawnRoom>,
-    looks: Query<LookBundle>,
+    players: Query<&Player>,
+    pending: Pending,
 ) {
+    // Registrations aren't pending until the end of the tick, so ones started this tick are
+    // tracked here as `(connection, username)`
+    let mut started = Vec::new();
     for command in comms.iter() {
-        if command.inner.args.len() < 2 {
-            send(
-                &mut commands,
-                command.conn,
-                Err("Usage: register <username> | <password>".to_owned()),
-            );
+        if pending.contains(command.conn) || started.iter().any(|(conn, _)| *conn == command.conn)
+        {
+            send(&mut commands, command.conn, Err(STILL_PENDING.to_owned()));
             continue;
         }
 
-        let username = command.inner.args[0].clone();
-        let password = command.inner.args[1].clone();
+        let username = command.args.text("username");
+        let password = command.args.text("password").to_owned();
 
-        if players
-            .iter()
-            .any(|(_, player)| player.username == username)
+        if players.iter().any(|player| player.username == username)
+            || pending
+                .registrations
+                .iter()
+                .any(|reg| reg.username == username)
+            || started.iter().any(|(_, name)| *name == username)
         {
             send(
                 &mut commands,
@@ -121,29 +291,100 @@ fn handle_register(
             continue;
         }
 
+        // The first account on a fresh server runs it
+        let mut roles = Roles::default();
+        if players.is_empty() && pending.registrations.is_empty() && started.is_empty() {
+            roles.0.insert(Role::Admin);
+        }
+        started.push((command.conn, username));
+
+        let task = AsyncComputeTaskPool::get().spawn(async move { hash_password(&password) });
+        commands.spawn(PendingRegistration {
+            conn: command.conn,
+            username: username.to_owned(),
+            roles,
+            task,
+        });
+    }
+}
+
+/// Creates the players whose passwords have been hashed, and logs their connections in as them.
+fn finish_registrations(
+    mut commands: Commands,
+    mut registrations: Query<(Entity, &mut PendingRegistration)>,
+    open: Query<(), With<Connection>>,
+    conns: Query<(), With<PlayerConnection>>,
+    spawn_room: Res<SpawnRoom>,
+    looks: Query<LookBundle>,
+    exits: Query<&Exit>,
+) {
+    for (entity, mut reg) in registrations.iter_mut() {
+        let Some(result) = block_on(future::poll_once(&mut reg.task)) else {
+            continue;
+        };
+        commands.entity(entity).despawn();
+
+        let password_hash = match result {
+            Ok(password_hash) => password_hash,
+            Err(err) => {
+                error!("Can't hash password for {}: {}", reg.username, err);
+                send(
+                    &mut commands,
+                    reg.conn,
+                    Err("Your account couldn't be created. Please try again.".to_owned()),
+                );
+                continue;
+            }
+        };
+
+        // The player is made even if the connection closed in the meantime, since the username
+        // was already refused to anyone else
         let player_entity = commands
             .spawn((
                 Object::default(),
                 Player {
-                    username: username.clone(),
-                    password: password.clone(),
+                    username: std::mem::take(&mut reg.username),
+                    password_hash,
                 },
+                std::mem::take(&mut reg.roles),
             ))
             .set_parent(spawn_room.0)
             .id();
-        commands.entity(command.conn).insert(PlayerConnection {
+        // It may have resumed a session while the password was hashed
+        if !open.contains(reg.conn) || conns.contains(reg.conn) {
+            continue;
+        }
+        commands.entity(reg.conn).insert(PlayerConnection {
             object: player_entity,
         });
 
         let spawn_room_look = looks.get(spawn_room.0).unwrap();
 
-        send(&mut commands, command.conn, Ok(look(spawn_room_look)));
+        send_as(
+            &mut commands,
+            reg.conn,
+            MessageKind::Room,
+            Ok(look(spawn_room_look, &exits)),
+        );
     }
 }
 
-fn handle_logout(mut commands: Commands, comms: Query<&PlayerCommand, With<LogoutCommand>>) {
+fn handle_logout(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<LogoutCommand>>,
+    conns: Query<(&PlayerConnection, Option<&Session>)>,
+    mut sessions: Query<&mut Sessions>,
+) {
     for command in comms.iter() {
-        commands.entity(command.conn).remove::<PlayerConnection>();
+        if let Ok((conn, Some(session))) = conns.get(command.conn) {
+            if let Ok(mut sessions) = sessions.get_mut(conn.object) {
+                sessions.0.retain(|token| *token != session.0);
+            }
+        }
+
+        commands
+            .entity(command.conn)
+            .remove::<(PlayerConnection, Session)>();
         send(
             &mut commands,
             command.conn,
@@ -152,6 +393,232 @@ fn handle_logout(mut commands: Commands, comms: Query<&PlayerCommand, With<Logou
     }
 }
 
+#[allow(clippy::too_many_arguments)]
+fn handle_resume(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<ResumeCommand>>,
+    mut players: Query<(Entity, Option<&Parent>, &mut Sessions), With<Player>>,
+    by_player: Res<ConnectionsByPlayer>,
+    config: Res<ServerConfig>,
+    looks: Query<LookBundle>,
+    exits: Query<&Exit>,
+    pending: Pending,
+) {
+    let mut attached = Vec::new();
+    for command in comms.iter() {
+        if pending.contains(command.conn) {
+            send(&mut commands, command.conn, Err(STILL_PENDING.to_owned()));
+            continue;
+        }
+
+        let token = hash_token(command.args.text("token"));
+
+        let Some((player_entity, room, mut sessions)) = players
+            .iter_mut()
+            .find(|(_, _, sessions)| sessions.0.contains(&token))
+        else {
+            send(
+                &mut commands,
+                command.conn,
+                Err("Your session has expired. Please log in again.".to_owned()),
+            );
+            continue;
+        };
+
+        if let Err(err) = attach(
+            &mut commands,
+            &by_player,
+            &mut attached,
+            config.multi_login,
+            command.conn,
+            player_entity,
+        ) {
+            send(&mut commands, command.conn, Err(err));
+            continue;
+        }
+
+        // Tokens are single use, a fresh one is issued below
+        sessions.0.retain(|t| *t != token);
+
+        // Stashed players are shown the room when they're put back in it
+        if let Some(room_look) = room.and_then(|room| looks.get(room.get()).ok()) {
+            send_as(
+                &mut commands,
+                command.conn,
+                MessageKind::Room,
+                Ok(look(room_look, &exits)),
+            );
+        }
+    }
+}
+
+/// Logs `conn` in as `player`, deciding what happens to any connections already playing them.
+fn attach(
+    commands: &mut Commands,
+    by_player: &ConnectionsByPlayer,
+    attached: &mut Vec<(Entity, Entity)>,
+    policy: MultiLogin,
+    conn: Entity,
+    player: Entity,
+) -> Result<(), String> {
+    let existing = by_player
+        .of(player)
+        .iter()
+        .copied()
+        .chain(
+            attached
+                .iter()
+                .filter(|(_, object)| *object == player)
+                .map(|(conn, _)| *conn),
+        )
+        .collect::<Vec<_>>();
+    if policy == MultiLogin::Refuse && !existing.is_empty() {
+        return Err("That player is already logged in somewhere else.".to_owned());
+    }
+
+    // The new connection goes in first so the player is never left without one
+    commands
+        .entity(conn)
+        .insert(PlayerConnection { object: player });
+    attached.push((conn, player));
+    if policy == MultiLogin::TakeOver {
+        for old in existing {
+            kick(
+                commands,
+                old,
+                "You have logged in from somewhere else.".to_owned(),
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Gives connections that just logged in a token they can use to resume their session if they're
+/// disconnected. Only clients speaking the structured protocol can tell the token apart from
+/// other output, so plain text clients don't get one.
+fn issue_session_tokens(
+    mut commands: Commands,
+    conns: Query<(Entity, &PlayerConnection, Option<&WireFormat>), Added<PlayerConnection>>,
+    mut sessions: Query<&mut Sessions>,
+) {
+    for (conn, player_conn, format) in conns.iter() {
+        if format != Some(&WireFormat::Json) {
+            continue;
+        }
+        let Ok(mut sessions) = sessions.get_mut(player_conn.object) else {
+            continue;
+        };
+
+        let token = generate_token();
+        let hash = hash_token(&token);
+        sessions.0.push(hash.clone());
+        let excess = sessions.0.len().saturating_sub(MAX_SESSIONS);
+        sessions.0.drain(..excess);
+
+        commands.entity(conn).insert(Session(hash));
+        send_direct(&mut commands, conn, MessageKind::Session, Ok(token));
+    }
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    hex(&bytes)
+}
+
+/// Hashes a session token for keeping in [`Sessions`]. Tokens are random enough that they don't
+/// need a salt or a slow hash like passwords do.
+fn hash_token(token: &str) -> String {
+    hex(&Sha256::digest(token.as_bytes()))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Hashes a password into a salted PHC string suitable for `Player::password_hash`.
+pub fn hash_password(password: &str) -> password_hash::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+}
+
+/// A hash of nothing in particular, to check passwords against when there's no account to check
+/// them against.
+static DUMMY_PASSWORD_HASH: LazyLock<String> =
+    LazyLock::new(|| hash_password("").expect("Can't hash password"));
+
+/// Checks a password against a stored hash. Records saved before hashing was introduced hold the
+/// plaintext password instead, which are still accepted so they can be migrated on login.
+pub fn verify_password(password_hash: &str, password: &str) -> bool {
+    match PasswordHash::new(password_hash) {
+        Ok(hash) => Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok(),
+        Err(_) => {
+            // Plaintext records take as long to check as hashed ones, so they can't be told apart
+            verify_password(DUMMY_PASSWORD_HASH.as_str(), password);
+            constant_time_eq(password_hash.as_bytes(), password.as_bytes())
+        }
+    }
+}
+
+/// Checks a password for logging in, hashing it if it matched a legacy plaintext record.
+fn check_password(password_hash: &str, password: &str) -> PasswordCheck {
+    if !verify_password(password_hash, password) {
+        PasswordCheck::Wrong
+    } else if PasswordHash::new(password_hash).is_err() {
+        // Left in plaintext to be tried again next login if it can't be hashed
+        hash_password(password).map_or(PasswordCheck::Right, PasswordCheck::Migrated)
+    } else {
+        PasswordCheck::Right
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+const STILL_PENDING: &str = "Your last login hasn't finished yet.";
+
+/// Commands whose arguments are a password or a session token.
+const CREDENTIAL_COMMANDS: [&str; 3] = ["login", "register", "resume"];
+
+/// Strips the arguments from a message so it can be logged safely, if the command it starts with
+/// could be an abbreviation of one that takes credentials.
+pub fn redact_credentials(message: &str) -> &str {
+    let command = CommandInner::parse(message).command;
+    if !command.is_empty()
+        && CREDENTIAL_COMMANDS
+            .iter()
+            .any(|credential| credential.starts_with(&command))
+    {
+        message.split_whitespace().next().unwrap_or_default()
+    } else {
+        message
+    }
+}
+
+/// Updates `app` until every login and registration has finished checking its password.
+#[cfg(test)]
+pub(crate) fn update_until_checked(app: &mut App) {
+    loop {
+        app.update();
+        let world = app.world_mut();
+        if world
+            .query_filtered::<(), Or<(With<PendingLogin>, With<PendingRegistration>)>>()
+            .iter(world)
+            .next()
+            .is_none()
+        {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(1));
+    }
+}
+
 #[derive(Component, Debug, Default)]
 pub struct RequiresLogin;
 
@@ -160,8 +627,37 @@ pub struct RequiresNoLogin;
 
 #[cfg(test)]
 mod tests {
-    use crate::login::LoginPlugin;
-    use crate::PlayerCommand;
+    use argon2::PasswordHash;
+    use bevy::prelude::*;
+
+    use crate::config::{MultiLogin, ServerConfig};
+    use crate::login::{
+        hash_password, hash_token, redact_credentials, update_until_checked, verify_password,
+        LoginPlugin, Sessions, DUMMY_PASSWORD_HASH, STILL_PENDING,
+    };
+    use crate::protocol::WireFormat;
+    use crate::roles::{Role, Roles};
+    use crate::{MessageKind, Player, PlayerCommand, PlayerConnection};
+
+    /// Registers over the structured protocol and returns the session token that was issued.
+    fn register_with_session(
+        app: &mut App,
+        conn: Entity,
+        rx: &std::sync::mpsc::Receiver<crate::ConnectionMessageEvent>,
+    ) -> String {
+        app.world_mut().entity_mut(conn).insert(WireFormat::Json);
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        update_until_checked(app);
+        rx.try_recv().unwrap();
+
+        let msg = rx.try_recv().unwrap();
+        assert_eq!(msg.1, MessageKind::Session);
+        msg.0.unwrap()
+    }
 
     #[test]
     fn register_works() {
@@ -173,11 +669,20 @@ mod tests {
             vec!["test", "password"],
             conn,
         ));
-        app.update();
+        update_until_checked(&mut app);
 
         assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
     }
 
+    #[test]
+    fn credentials_are_redacted_however_login_is_typed() {
+        assert_eq!(redact_credentials("login alice | pw"), "login");
+        assert_eq!(redact_credentials("logi alice | pw"), "logi");
+        assert_eq!(redact_credentials("  login\talice|pw"), "login");
+        assert_eq!(redact_credentials("res token"), "res");
+        assert_eq!(redact_credentials("help me"), "help me");
+    }
+
     #[test]
     fn registering_with_existing_username_fails() {
         let (mut app, conn, rx, conns) = crate::test_app::<1>();
@@ -188,15 +693,43 @@ mod tests {
             vec!["test", "password"],
             conns[0],
         ));
-        app.update();
+        update_until_checked(&mut app);
         app.world_mut().spawn(PlayerCommand::new(
             "register",
             vec!["test", "password"],
             conn,
         ));
-        app.update();
+        update_until_checked(&mut app);
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+    }
+
+    #[test]
+    fn registering_in_the_same_tick_checks_each_other() {
+        let (mut app, conn, rx, conns) = crate::test_app::<2>();
+        app.add_plugins(LoginPlugin);
+
+        for (conn, username) in [(conns[0], "first"), (conns[1], "second"), (conn, "first")] {
+            app.world_mut().spawn(PlayerCommand::new(
+                "register",
+                vec![username, "password"],
+                conn,
+            ));
+        }
+        update_until_checked(&mut app);
 
         assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        let world = app.world_mut();
+        let mut admins = world
+            .query::<(&Player, &Roles)>()
+            .iter(world)
+            .map(|(player, roles)| (player.username.clone(), roles.has(Role::Admin)))
+            .collect::<Vec<_>>();
+        admins.sort();
+        assert_eq!(
+            admins,
+            vec![("first".to_owned(), true), ("second".to_owned(), false)]
+        );
     }
 
     #[test]
@@ -206,11 +739,28 @@ mod tests {
 
         app.world_mut()
             .spawn((PlayerCommand::new("register", vec![], conn),));
-        app.update();
+        update_until_checked(&mut app);
 
         assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
     }
 
+    #[test]
+    fn registering_with_too_many_args_shows_usage() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(LoginPlugin);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password", "extra"],
+            conn,
+        ));
+        update_until_checked(&mut app);
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0 == Err("Usage: register <username> | <password>".to_owned())));
+    }
+
     #[test]
     fn registering_when_logged_in_fails() {
         let (mut app, conn, rx, _conns) = crate::test_app::<0>();
@@ -221,7 +771,7 @@ mod tests {
             vec!["test", "password"],
             conn,
         ));
-        app.update();
+        update_until_checked(&mut app);
         rx.try_recv().unwrap();
 
         app.world_mut().spawn(PlayerCommand::new(
@@ -229,7 +779,7 @@ mod tests {
             vec!["test", "password"],
             conn,
         ));
-        app.update();
+        update_until_checked(&mut app);
 
         assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
     }
@@ -244,7 +794,7 @@ mod tests {
             vec!["test", "password"],
             conn,
         ));
-        app.update();
+        update_until_checked(&mut app);
         rx.try_recv().unwrap();
 
         app.world_mut()
@@ -276,7 +826,7 @@ mod tests {
             vec!["test", "password"],
             conn,
         ));
-        app.update();
+        update_until_checked(&mut app);
         rx.try_recv().unwrap();
 
         app.world_mut()
@@ -286,7 +836,7 @@ mod tests {
 
         app.world_mut()
             .spawn(PlayerCommand::new("login", vec!["test", "password"], conn));
-        app.update();
+        update_until_checked(&mut app);
 
         assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
     }
@@ -301,7 +851,7 @@ mod tests {
             vec!["test", "password"],
             conn,
         ));
-        app.update();
+        update_until_checked(&mut app);
         rx.try_recv().unwrap();
 
         app.world_mut()
@@ -311,7 +861,7 @@ mod tests {
 
         app.world_mut()
             .spawn(PlayerCommand::new("login", vec![], conn));
-        app.update();
+        update_until_checked(&mut app);
 
         assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
     }
@@ -326,7 +876,7 @@ mod tests {
             vec!["test", "password"],
             conn,
         ));
-        app.update();
+        update_until_checked(&mut app);
         rx.try_recv().unwrap();
 
         app.world_mut()
@@ -336,13 +886,13 @@ mod tests {
 
         app.world_mut()
             .spawn(PlayerCommand::new("login", vec!["test", "passwor"], conn));
-        app.update();
+        update_until_checked(&mut app);
 
         assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
     }
 
     #[test]
-    fn logging_in_when_already_logged_in_fails() {
+    fn logging_in_while_a_login_is_pending_fails() {
         let (mut app, conn, rx, _conns) = crate::test_app::<0>();
         app.add_plugins(LoginPlugin);
 
@@ -351,13 +901,293 @@ mod tests {
             vec!["test", "password"],
             conn,
         ));
+        update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("logout", vec![], conn));
         app.update();
         rx.try_recv().unwrap();
 
+        for _ in 0..2 {
+            app.world_mut()
+                .spawn(PlayerCommand::new("login", vec!["test", "password"], conn));
+        }
+        update_until_checked(&mut app);
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0 == Err(STILL_PENDING.to_owned())));
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+    }
+
+    #[test]
+    fn logging_in_when_already_logged_in_fails() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(LoginPlugin);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("login", vec!["test", "password"], conn));
+        update_until_checked(&mut app);
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+    }
+
+    #[test]
+    fn passwords_are_not_stored_in_plaintext() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(LoginPlugin);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        let world = app.world_mut();
+        let player = world.query::<&Player>().single(world);
+        assert!(!player.password_hash.contains("password"));
+        assert!(verify_password(&player.password_hash, "password"));
+    }
+
+    #[test]
+    fn hashes_are_salted() {
+        assert_ne!(
+            hash_password("password").unwrap(),
+            hash_password("password").unwrap()
+        );
+    }
+
+    #[test]
+    fn unknown_usernames_are_checked_against_a_real_hash() {
+        assert!(PasswordHash::new(&DUMMY_PASSWORD_HASH).is_ok());
+        assert!(!verify_password(&DUMMY_PASSWORD_HASH, "password"));
+
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(LoginPlugin);
+        app.world_mut().spawn(PlayerCommand::new(
+            "login",
+            vec!["nobody", "password"],
+            conn,
+        ));
+        update_until_checked(&mut app);
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_err_and(|msg| msg == "Invalid username or password.")));
+    }
+
+    #[test]
+    fn plaintext_passwords_are_migrated_on_login() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(LoginPlugin);
+
+        app.world_mut().spawn(Player {
+            username: "test".to_owned(),
+            password_hash: "password".to_owned(),
+        });
         app.world_mut()
             .spawn(PlayerCommand::new("login", vec!["test", "password"], conn));
+        update_until_checked(&mut app);
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+        let world = app.world_mut();
+        let player = world.query::<&Player>().single(world);
+        assert_ne!(player.password_hash, "password");
+        assert!(verify_password(&player.password_hash, "password"));
+    }
+
+    #[test]
+    fn resume_works() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins(LoginPlugin);
+        let token = register_with_session(&mut app, conn, &rx);
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("resume", vec![&token], conns[0]));
+        app.update();
+
+        let world = app.world_mut();
+        let player = world.query::<(Entity, &Player)>().single(world).0;
+        assert_eq!(
+            world.get::<PlayerConnection>(conns[0]).map(|pc| pc.object),
+            Some(player)
+        );
+        assert!(!world
+            .get::<Sessions>(player)
+            .unwrap()
+            .0
+            .contains(&hash_token(&token)));
+    }
+
+    #[test]
+    fn session_tokens_are_stored_hashed() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(LoginPlugin);
+        let token = register_with_session(&mut app, conn, &rx);
+
+        let world = app.world_mut();
+        let sessions = world.query::<&Sessions>().single(world);
+        assert_eq!(sessions.0, vec![hash_token(&token)]);
+    }
+
+    #[test]
+    fn resuming_while_registering_fails() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(LoginPlugin);
+        let token = register_with_session(&mut app, conn, &rx);
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("logout", vec![], conn));
+        app.update();
+        rx.try_recv().unwrap();
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["other", "password"],
+            conn,
+        ));
+        app.update();
+        app.world_mut()
+            .spawn(PlayerCommand::new("resume", vec![&token], conn));
+        update_until_checked(&mut app);
+
+        assert!(rx
+            .try_iter()
+            .any(|msg| msg.0 == Err(STILL_PENDING.to_owned())));
+        let world = app.world_mut();
+        let other = world
+            .query::<(Entity, &Player)>()
+            .iter(world)
+            .find(|(_, player)| player.username == "other")
+            .unwrap()
+            .0;
+        assert_eq!(
+            world.get::<PlayerConnection>(conn).map(|pc| pc.object),
+            Some(other)
+        );
+    }
+
+    #[test]
+    fn resuming_after_logout_fails() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(LoginPlugin);
+        let token = register_with_session(&mut app, conn, &rx);
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("logout", vec![], conn));
+        app.update();
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("resume", vec![&token], conn));
         app.update();
 
         assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        assert!(app.world().get::<PlayerConnection>(conn).is_none());
+    }
+
+    /// Registers on `conn`, then logs in as the same player from another connection.
+    fn log_in_twice(
+        multi_login: MultiLogin,
+    ) -> (
+        App,
+        Entity,
+        std::sync::mpsc::Receiver<crate::ConnectionMessageEvent>,
+        Entity,
+    ) {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins(LoginPlugin).insert_resource(ServerConfig {
+            multi_login,
+            ..default()
+        });
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "login",
+            vec!["test", "password"],
+            conns[0],
+        ));
+        update_until_checked(&mut app);
+
+        (app, conn, rx, conns[0])
+    }
+
+    #[test]
+    fn second_login_takes_over() {
+        let (app, conn, rx, other_conn) = log_in_twice(MultiLogin::TakeOver);
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err() && msg.1 == MessageKind::Disconnect));
+        assert!(rx.try_recv().is_err());
+        assert!(app.world().get::<PlayerConnection>(conn).is_none());
+        assert!(app.world().get::<PlayerConnection>(other_conn).is_some());
+    }
+
+    #[test]
+    fn second_login_is_mirrored() {
+        let (app, conn, rx, other_conn) = log_in_twice(MultiLogin::Mirror);
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "Successfully logged in.")));
+        assert!(app.world().get::<PlayerConnection>(conn).is_some());
+        assert!(app.world().get::<PlayerConnection>(other_conn).is_some());
+    }
+
+    #[test]
+    fn second_login_is_refused() {
+        let (app, conn, rx, other_conn) = log_in_twice(MultiLogin::Refuse);
+
+        assert!(rx.try_recv().is_err());
+        assert!(app.world().get::<PlayerConnection>(conn).is_some());
+        assert!(app.world().get::<PlayerConnection>(other_conn).is_none());
+    }
+
+    #[test]
+    fn logins_in_the_same_tick_are_refused_too() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins(LoginPlugin).insert_resource(ServerConfig {
+            multi_login: MultiLogin::Refuse,
+            ..default()
+        });
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        update_until_checked(&mut app);
+        app.world_mut()
+            .spawn(PlayerCommand::new("logout", vec![], conn));
+        app.update();
+        while rx.try_recv().is_ok() {}
+
+        for conn in [conn, conns[0]] {
+            app.world_mut()
+                .spawn(PlayerCommand::new("login", vec!["test", "password"], conn));
+        }
+        update_until_checked(&mut app);
+
+        let world = app.world_mut();
+        assert_eq!(world.query::<&PlayerConnection>().iter(world).count(), 1);
     }
 }
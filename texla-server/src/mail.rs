@@ -0,0 +1,584 @@
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::markup::escape;
+use crate::prelude::*;
+
+pub struct MailPlugin;
+
+impl Plugin for MailPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                (
+                    preprocess_commands::<MailCommand>,
+                    preprocess_commands::<MailListCommand>,
+                    preprocess_commands::<MailReadCommand>,
+                    preprocess_commands::<MailDeleteCommand>,
+                    preprocess_commands::<MailSendCommand>,
+                )
+                    .in_set(PreprocessCommandsSet),
+                (
+                    handle_mail,
+                    handle_mail_read,
+                    handle_mail_delete,
+                    handle_mail_send,
+                )
+                    .in_set(HandleCommandsSet),
+                notify_unread_mail.after(HandleCommandsSet),
+            ),
+        );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        CommandHandler::<MailCommand>::new("mail")
+            .with_summary("Lists the mail you've received.")
+            .with_help("Use `mail read`, `mail delete` and `mail send` for the rest."),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<MailListCommand>::new("mail list")
+            .with_summary("Lists the mail you've received, like `mail` does."),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<MailReadCommand>::new("mail read")
+            .with_args([Arg::new("number", ArgKind::Integer)])
+            .with_summary("Reads a message from your mailbox."),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<MailDeleteCommand>::new("mail delete")
+            .with_args([Arg::new("number", ArgKind::Integer)])
+            .with_summary("Deletes a message from your mailbox."),
+        RequiresLogin,
+    ));
+    commands.spawn((
+        CommandHandler::<MailSendCommand>::new("mail send")
+            .with_summary("Mails the last page you sent to someone offline."),
+        RequiresLogin,
+    ));
+}
+
+#[derive(Component, Default)]
+struct MailCommand;
+
+#[derive(Component, Default)]
+struct MailListCommand;
+
+#[derive(Component, Default)]
+struct MailReadCommand;
+
+#[derive(Component, Default)]
+struct MailDeleteCommand;
+
+#[derive(Component, Default)]
+struct MailSendCommand;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Mail {
+    pub from: String,
+    pub body: String,
+    pub read: bool,
+}
+
+/// The mail a player has received, oldest first.
+#[derive(Component, Debug, Default, Clone)]
+pub struct Mailbox(pub Vec<Mail>);
+
+impl Mailbox {
+    pub fn unread(&self) -> usize {
+        self.0.iter().filter(|mail| !mail.read).count()
+    }
+}
+
+/// How many messages a mailbox can hold before mail to it is refused.
+const MAX_MAIL: usize = 100;
+
+/// A message that couldn't be delivered because its recipient was offline, waiting for the sender
+/// to confirm it should be mailed instead. It's kept on the sender's player.
+#[derive(Component, Debug)]
+pub struct PendingMail {
+    pub to: String,
+    pub body: String,
+}
+
+fn handle_mail(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, Or<(With<MailCommand>, With<MailListCommand>)>>,
+    conns: Query<&PlayerConnection>,
+    mailboxes: Query<&Mailbox>,
+) {
+    for command in comms.iter() {
+        let Ok(conn) = conns.get(command.conn) else {
+            continue;
+        };
+        let Ok(mailbox) = mailboxes.get(conn.object) else {
+            continue;
+        };
+        send(&mut commands, command.conn, Ok(list_mail(mailbox)));
+    }
+}
+
+fn handle_mail_read(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<MailReadCommand>>,
+    conns: Query<&PlayerConnection>,
+    mut mailboxes: Query<&mut Mailbox>,
+) {
+    for command in comms.iter() {
+        let Ok(conn) = conns.get(command.conn) else {
+            continue;
+        };
+        let Ok(mut mailbox) = mailboxes.get_mut(conn.object) else {
+            continue;
+        };
+        let result = to_index(command.args.integer("number"), &mailbox).map(|index| {
+            let mail = &mut mailbox.0[index];
+            mail.read = true;
+            format!("From {}:\n{}", escape(&mail.from), escape(&mail.body))
+        });
+        send(&mut commands, command.conn, result);
+    }
+}
+
+fn handle_mail_delete(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<MailDeleteCommand>>,
+    conns: Query<&PlayerConnection>,
+    mut mailboxes: Query<&mut Mailbox>,
+) {
+    for command in comms.iter() {
+        let Ok(conn) = conns.get(command.conn) else {
+            continue;
+        };
+        let Ok(mut mailbox) = mailboxes.get_mut(conn.object) else {
+            continue;
+        };
+        let result = to_index(command.args.integer("number"), &mailbox).map(|index| {
+            mailbox.0.remove(index);
+            format!("Deleted message {}.", index + 1)
+        });
+        send(&mut commands, command.conn, result);
+    }
+}
+
+fn handle_mail_send(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<MailSendCommand>>,
+    conns: Query<&PlayerConnection>,
+    drafts: Query<&PendingMail>,
+    mut players: Query<(&Player, &mut Mailbox)>,
+) {
+    for command in comms.iter() {
+        let Ok(conn) = conns.get(command.conn) else {
+            continue;
+        };
+        let Ok(pending) = drafts.get(conn.object) else {
+            send(
+                &mut commands,
+                command.conn,
+                Err("You have no message waiting to be mailed.".to_owned()),
+            );
+            continue;
+        };
+
+        let Ok((from, _)) = players.get(conn.object) else {
+            continue;
+        };
+        let mail = Mail {
+            from: from.username.clone(),
+            body: pending.body.clone(),
+            read: false,
+        };
+        commands.entity(conn.object).remove::<PendingMail>();
+        let result = deliver_mail(&mut players, &pending.to, mail);
+        send(&mut commands, command.conn, result);
+    }
+}
+
+fn list_mail(mailbox: &Mailbox) -> String {
+    if mailbox.0.is_empty() {
+        return "You have no mail.".to_owned();
+    }
+
+    mailbox
+        .0
+        .iter()
+        .enumerate()
+        .map(|(i, mail)| {
+            let preview = mail.body.lines().next().unwrap_or_default();
+            format!(
+                "{}{} {}: {}",
+                if mail.read { " " } else { "*" },
+                i + 1,
+                escape(&mail.from),
+                escape(preview)
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Turns a 1-based message number as typed by the player into an index into the mailbox.
+fn to_index(number: i64, mailbox: &Mailbox) -> Result<usize, String> {
+    usize::try_from(number)
+        .ok()
+        .filter(|n| *n >= 1 && *n <= mailbox.0.len())
+        .map(|n| n - 1)
+        .ok_or_else(|| format!("There is no message {}.", number))
+}
+
+fn deliver_mail(
+    players: &mut Query<(&Player, &mut Mailbox)>,
+    to: &str,
+    mail: Mail,
+) -> Result<String, String> {
+    let Some((player, mut mailbox)) = players.iter_mut().find(|(player, _)| player.username == to)
+    else {
+        return Err(format!("No player named {}.", escape(to)));
+    };
+    if mailbox.0.len() >= MAX_MAIL {
+        return Err(format!("{}'s mailbox is full.", escape(&player.username)));
+    }
+
+    mailbox.0.push(mail);
+    Ok(format!("Mailed {}.", escape(&player.username)))
+}
+
+fn notify_unread_mail(
+    mut commands: Commands,
+    conns: Query<(Entity, &PlayerConnection), Added<PlayerConnection>>,
+    mailboxes: Query<&Mailbox>,
+) {
+    for (conn, player_conn) in conns.iter() {
+        let Ok(mailbox) = mailboxes.get(player_conn.object) else {
+            continue;
+        };
+        match mailbox.unread() {
+            0 => {}
+            1 => send(
+                &mut commands,
+                conn,
+                Ok("You have 1 unread message. Type `mail` to see it.".to_owned()),
+            ),
+            n => send(
+                &mut commands,
+                conn,
+                Ok(format!(
+                    "You have {} unread messages. Type `mail` to see them.",
+                    n
+                )),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::chat::ChatPlugin;
+    use crate::login::LoginPlugin;
+    use crate::mail::{Mail, MailPlugin, Mailbox, MAX_MAIL};
+    use crate::{Player, PlayerCommand};
+
+    fn mailbox_of(app: &mut App, username: &str) -> Mailbox {
+        let world = app.world_mut();
+        world
+            .query::<(&Player, &Mailbox)>()
+            .iter(world)
+            .find(|(player, _)| player.username == username)
+            .map(|(_, mailbox)| mailbox.clone())
+            .unwrap()
+    }
+
+    #[test]
+    fn send_and_read_works() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, ChatPlugin, MailPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["reader", "password"],
+            conn,
+        ));
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["sender", "password"],
+            conns[0],
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+        app.world_mut()
+            .spawn(PlayerCommand::new("logout", vec![], conn));
+        app.update();
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("page reader | hello", conns[0]));
+        app.update();
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail send", conns[0]));
+        app.update();
+        assert_eq!(mailbox_of(&mut app, "reader").unread(), 1);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "login",
+            vec!["reader", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        while rx.try_recv().is_ok() {}
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail read 1", conn));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "From sender:\nhello")));
+        assert_eq!(mailbox_of(&mut app, "reader").unread(), 0);
+    }
+
+    #[test]
+    fn delete_works() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MailPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["reader", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        let world = app.world_mut();
+        let mut mailbox = world.query::<&mut Mailbox>().single_mut(world);
+        mailbox.0.push(Mail {
+            from: "someone".to_owned(),
+            body: "hello".to_owned(),
+            read: false,
+        });
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail delete 1", conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+        assert!(mailbox_of(&mut app, "reader").0.is_empty());
+    }
+
+    #[test]
+    fn reading_missing_mail_fails() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MailPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["reader", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail read 1", conn));
+        app.update();
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == "There is no message 1.")));
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail read", conn));
+        app.update();
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == "Usage: mail read <number>")));
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail read first", conn));
+        app.update();
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+    }
+
+    #[test]
+    fn mail_is_typed_like_a_sentence() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MailPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["reader", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        let world = app.world_mut();
+        let mut mailbox = world.query::<&mut Mailbox>().single_mut(world);
+        mailbox.0.push(Mail {
+            from: "someone".to_owned(),
+            body: "hello".to_owned(),
+            read: false,
+        });
+
+        for line in ["mail", "mail list", "ma list"] {
+            app.world_mut().spawn(PlayerCommand::parse(line, conn));
+            app.update();
+            assert!(rx
+                .try_recv()
+                .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "*1 someone: hello")));
+        }
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("ma read 1", conn));
+        app.update();
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "From someone:\nhello")));
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail frobnicate", conn));
+        app.update();
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == "Usage: mail")));
+    }
+
+    #[test]
+    fn unread_mail_is_announced_at_login() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MailPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["reader", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+        app.world_mut()
+            .spawn(PlayerCommand::new("logout", vec![], conn));
+        app.update();
+        rx.try_recv().unwrap();
+
+        let world = app.world_mut();
+        let mut mailbox = world.query::<&mut Mailbox>().single_mut(world);
+        mailbox.0.push(Mail {
+            from: "someone".to_owned(),
+            body: "hello".to_owned(),
+            read: false,
+        });
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "login",
+            vec!["reader", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.update();
+        rx.try_recv().unwrap();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg.contains("1 unread message"))));
+    }
+
+    #[test]
+    fn drafts_stay_with_the_player_that_wrote_them() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, ChatPlugin, MailPlugin));
+
+        for (conn, username) in [(conn, "writer"), (conns[0], "away")] {
+            app.world_mut().spawn(PlayerCommand::new(
+                "register",
+                vec![username, "password"],
+                conn,
+            ));
+        }
+        crate::login::update_until_checked(&mut app);
+        for conn in [conns[0], conn] {
+            app.world_mut()
+                .spawn(PlayerCommand::new("logout", vec![], conn));
+            app.update();
+        }
+        app.world_mut().spawn(PlayerCommand::new(
+            "login",
+            vec!["writer", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("page away | secret", conn));
+        app.update();
+        app.world_mut()
+            .spawn(PlayerCommand::new("logout", vec![], conn));
+        app.update();
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["someone", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        while rx.try_recv().is_ok() {}
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail send", conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        assert_eq!(mailbox_of(&mut app, "away").0.len(), 0);
+    }
+
+    #[test]
+    fn full_mailboxes_refuse_mail() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, ChatPlugin, MailPlugin));
+
+        for (conn, username) in [(conn, "sender"), (conns[0], "away")] {
+            app.world_mut().spawn(PlayerCommand::new(
+                "register",
+                vec![username, "password"],
+                conn,
+            ));
+        }
+        crate::login::update_until_checked(&mut app);
+        app.world_mut()
+            .spawn(PlayerCommand::new("logout", vec![], conns[0]));
+        app.update();
+
+        let world = app.world_mut();
+        let mut mailbox = world
+            .query::<(&Player, &mut Mailbox)>()
+            .iter_mut(world)
+            .find(|(player, _)| player.username == "away")
+            .unwrap()
+            .1;
+        mailbox.0 = vec![
+            Mail {
+                from: "someone".to_owned(),
+                body: "hello".to_owned(),
+                read: false,
+            };
+            MAX_MAIL
+        ];
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("page away | hi", conn));
+        app.update();
+        while rx.try_recv().is_ok() {}
+        app.world_mut()
+            .spawn(PlayerCommand::parse("mail send", conn));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0 == Err("away's mailbox is full.".to_owned())));
+        assert_eq!(mailbox_of(&mut app, "away").0.len(), MAX_MAIL);
+    }
+}
@@ -1,5 +1,17 @@
 use texla_server::app;
+use texla_server::config::{ConfigError, ServerConfig, USAGE};
 
 fn main() {
-    app().run();
+    let config = match ServerConfig::load(std::env::args().skip(1), |key| std::env::var(key).ok()) {
+        Ok(config) => config,
+        Err(ConfigError::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    app(config).run();
 }
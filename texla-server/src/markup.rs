@@ -0,0 +1,75 @@
+//! Tags that style the text after them, like `{bold}The Void{reset}`, for clients that can show
+//! them. Anything in braces that isn't a tag is left as typed, and `{{` is a literal `{`.
+
+/// Every tag, without its braces. `reset` goes back to plain text.
+pub const TAGS: [&str; 13] = [
+    "reset",
+    "bold",
+    "italic",
+    "underline",
+    "red",
+    "green",
+    "yellow",
+    "blue",
+    "magenta",
+    "cyan",
+    "white",
+    "gray",
+    "dark_yellow",
+];
+
+/// Makes `text` show as typed, even if it has braces in it, for putting what players wrote into
+/// messages.
+pub fn escape(text: &str) -> String {
+    text.replace('{', "{{")
+}
+
+/// Removes the tags from `text`, for connections that can't show them.
+pub fn strip(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('{') {
+        stripped.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(after) = rest.strip_prefix("{{") {
+            stripped.push('{');
+            rest = after;
+            continue;
+        }
+        match rest.find('}').map(|end| &rest[1..end]) {
+            Some(tag) if TAGS.contains(&tag) => rest = &rest[tag.len() + 2..],
+            _ => {
+                stripped.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    stripped.push_str(rest);
+    stripped
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::markup::{escape, strip};
+
+    #[test]
+    fn tags_are_stripped() {
+        assert_eq!(strip("{bold}The Void{reset}"), "The Void");
+        assert_eq!(strip("{red}{underline}hot{reset}!"), "hot!");
+    }
+
+    #[test]
+    fn other_braces_are_kept() {
+        assert_eq!(strip("{smiles} {{bold}"), "{smiles} {bold}");
+        assert_eq!(strip("{bold"), "{bold");
+        assert_eq!(strip("a {} b }"), "a {} b }");
+    }
+
+    #[test]
+    fn escaped_text_is_stripped_back_to_itself() {
+        for text in ["{red}", "{{bold}", "a { b }", "{"] {
+            assert_eq!(strip(&escape(text)), text);
+        }
+    }
+}
@@ -0,0 +1,335 @@
+use bevy::prelude::*;
+
+use crate::interact::{look, LookBundle};
+use crate::markup::escape;
+use crate::prelude::*;
+
+pub struct MovementPlugin;
+
+impl Plugin for MovementPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                (preprocess_commands::<GoCommand>,).in_set(PreprocessCommandsSet),
+                (handle_go,).in_set(HandleCommandsSet),
+            ),
+        );
+    }
+}
+
+/// Bare direction commands, as `(short, long)` pairs. Either form moves through an exit named
+/// with either form.
+const DIRECTIONS: [(&str, &str); 6] = [
+    ("n", "north"),
+    ("s", "south"),
+    ("e", "east"),
+    ("w", "west"),
+    ("u", "up"),
+    ("d", "down"),
+];
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        CommandHandler::<GoCommand>::new("go")
+            .with_args([Arg::new("exit", ArgKind::Text)])
+            .with_summary("Goes through an exit.")
+            .with_help("Directions can be typed on their own too, like `n` or `north`."),
+        RequiresLogin,
+    ));
+    for (short, long) in DIRECTIONS {
+        commands.spawn((
+            CommandHandler::<GoCommand>::new(long)
+                .with_aliases([short])
+                .with_summary(&format!("Goes {}.", long)),
+            RequiresLogin,
+        ));
+    }
+}
+
+#[derive(Component, Default)]
+struct GoCommand;
+
+/// A one-way link from the room this entity is parented to into `destination`.
+#[derive(Component, Debug)]
+pub struct Exit {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub destination: Entity,
+}
+
+impl Exit {
+    pub fn matches(&self, name: &str) -> bool {
+        self.name.eq_ignore_ascii_case(name)
+            || self
+                .aliases
+                .iter()
+                .any(|alias| alias.eq_ignore_ascii_case(name))
+    }
+}
+
+/// Every name an exit could be called by when the player types `name`, so that `n` and `north`
+/// are interchangeable.
+fn exit_names(name: &str) -> Vec<&str> {
+    DIRECTIONS
+        .iter()
+        .find(|(short, long)| short.eq_ignore_ascii_case(name) || long.eq_ignore_ascii_case(name))
+        .map(|(short, long)| vec![*short, *long])
+        .unwrap_or_else(|| vec![name])
+}
+
+fn handle_go(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<GoCommand>>,
+    conns: Query<&PlayerConnection>,
+    player_parents: Query<&Parent, With<Player>>,
+    rooms: Query<&Children>,
+    exits: Query<&Exit>,
+    looks: Query<LookBundle>,
+) {
+    for command in comms.iter() {
+        let exit_name = if command.inner.command == "go" {
+            command.args.text("exit")
+        } else {
+            command.inner.command.as_str()
+        };
+
+        let Ok(conn) = conns.get(command.conn) else {
+            continue;
+        };
+        let Ok(player_parent) = player_parents.get(conn.object) else {
+            send(
+                &mut commands,
+                command.conn,
+                Err("You aren't anywhere.".to_owned()),
+            );
+            continue;
+        };
+        let names = exit_names(exit_name);
+
+        let Some(exit) = rooms
+            .get(player_parent.get())
+            .into_iter()
+            .flatten()
+            .filter_map(|child| exits.get(*child).ok())
+            .find(|exit| names.iter().any(|name| exit.matches(name)))
+        else {
+            send(
+                &mut commands,
+                command.conn,
+                Err(format!("You can't go {}.", escape(exit_name))),
+            );
+            continue;
+        };
+
+        let Ok(destination_look) = looks.get(exit.destination) else {
+            send(
+                &mut commands,
+                command.conn,
+                Err(format!("The way {} leads nowhere.", escape(&exit.name))),
+            );
+            continue;
+        };
+
+        commands.entity(conn.object).set_parent(exit.destination);
+        send_as(
+            &mut commands,
+            command.conn,
+            MessageKind::Room,
+            Ok(look(destination_look, &exits)),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::help::HelpPlugin;
+    use crate::login::LoginPlugin;
+    use crate::movement::{Exit, MovementPlugin};
+    use crate::{Object, Player, PlayerCommand, SpawnRoom};
+
+    fn dig(world: &mut World, from: Entity, name: &str, aliases: Vec<&str>) -> Entity {
+        let to = world
+            .spawn((Name::new(format!("Beyond {}", name)), Object::default()))
+            .id();
+        world
+            .spawn((
+                Object::default(),
+                Exit {
+                    name: name.to_owned(),
+                    aliases: aliases.into_iter().map(|s| s.to_owned()).collect(),
+                    destination: to,
+                },
+            ))
+            .set_parent(from);
+        to
+    }
+
+    fn player_room(app: &mut App) -> Entity {
+        let world = app.world_mut();
+        world
+            .query_filtered::<&Parent, With<Player>>()
+            .single(world)
+            .get()
+    }
+
+    #[test]
+    fn going_through_exit_works() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MovementPlugin));
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        let to = dig(app.world_mut(), spawn_room, "door", vec![]);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("go", vec!["door"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_ok_and(|msg| msg.starts_with("{bold}Beyond door{reset}"))));
+        assert_eq!(player_room(&mut app), to);
+    }
+
+    #[test]
+    fn direction_shortcuts_work() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MovementPlugin));
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        let to = dig(app.world_mut(), spawn_room, "north", vec![]);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut().spawn(PlayerCommand::new("n", vec![], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+        assert_eq!(player_room(&mut app), to);
+    }
+
+    #[test]
+    fn going_from_nowhere_fails() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MovementPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        let world = app.world_mut();
+        let player = world.query_filtered::<Entity, With<Player>>().single(world);
+        world.entity_mut(player).remove_parent();
+
+        app.world_mut().spawn(PlayerCommand::new("n", vec![], conn));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == "You aren't anywhere.")));
+    }
+
+    #[test]
+    fn directions_are_listed_once() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, HelpPlugin, MovementPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("help", vec![], conn));
+        app.update();
+
+        let help = rx.try_recv().unwrap().0.unwrap();
+        assert_eq!(help.matches("Goes north.").count(), 1);
+        assert!(!help.lines().any(|line| line.trim_start().starts_with("n ")));
+    }
+
+    #[test]
+    fn going_through_alias_works() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MovementPlugin));
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        let to = dig(app.world_mut(), spawn_room, "trapdoor", vec!["down"]);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut().spawn(PlayerCommand::new("d", vec![], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+        assert_eq!(player_room(&mut app), to);
+    }
+
+    #[test]
+    fn going_through_missing_exit_fails() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MovementPlugin));
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("go", vec!["door"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        assert_eq!(player_room(&mut app), spawn_room);
+    }
+
+    #[test]
+    fn look_lists_exits() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, MovementPlugin));
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        dig(app.world_mut(), spawn_room, "north", vec![]);
+        dig(app.world_mut(), spawn_room, "east", vec![]);
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_ok_and(|msg| msg.ends_with("Exits: {cyan}north, east{reset}"))));
+    }
+}
@@ -0,0 +1,167 @@
+use std::fmt;
+
+/// Something wrong with the way a command was typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseError {
+    UnterminatedQuote,
+    TrailingBackslash,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::UnterminatedQuote => write!(f, "Missing a closing quote."),
+            ParseError::TrailingBackslash => write!(f, "Nothing to escape after the backslash."),
+        }
+    }
+}
+
+/// Splits arguments on `|`. Anything in double quotes is kept as is, pipes and surrounding spaces
+/// included, and a backslash makes the character after it literal. Unquoted whitespace at either
+/// end of an argument is dropped.
+pub fn split_args(str: &str) -> Result<Vec<String>, ParseError> {
+    split_leading_args(str, usize::MAX).map(|(args, _)| args)
+}
+
+/// Splits off the first `count` arguments like [`split_args`], and returns whatever's after the
+/// pipe that ends them as typed, for free text that follows them.
+pub fn split_leading_args(str: &str, count: usize) -> Result<(Vec<String>, &str), ParseError> {
+    if count == 0 {
+        return Ok((Vec::new(), str.trim()));
+    }
+
+    let mut args = Vec::new();
+    // Each character along with whether it was quoted or escaped, so trimming can skip it
+    let mut arg = Vec::<(char, bool)>::new();
+    let mut quoted = false;
+    let mut chars = str.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => arg.push((chars.next().ok_or(ParseError::TrailingBackslash)?.1, true)),
+            '"' => quoted = !quoted,
+            '|' if !quoted => {
+                args.push(trim_arg(std::mem::take(&mut arg)));
+                if args.len() == count {
+                    return Ok((args, str[i + 1..].trim()));
+                }
+            }
+            c => arg.push((c, quoted)),
+        }
+    }
+    if quoted {
+        return Err(ParseError::UnterminatedQuote);
+    }
+    args.push(trim_arg(arg));
+
+    Ok((args, ""))
+}
+
+fn trim_arg(arg: Vec<(char, bool)>) -> String {
+    let kept = |(c, literal): &(char, bool)| *literal || !c.is_whitespace();
+    let start = arg.iter().position(kept).unwrap_or(arg.len());
+    let end = arg.iter().rposition(kept).map_or(start, |i| i + 1);
+    arg[start..end].iter().map(|(c, _)| c).collect()
+}
+
+/// Writes arguments out so that [`split_args`] reads them back the same.
+pub fn join_args(args: &[impl AsRef<str>]) -> String {
+    args.iter()
+        .map(|arg| quote(arg.as_ref()))
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Quotes an argument, if it needs it to survive [`split_args`].
+pub fn quote(arg: &str) -> String {
+    let needs_quotes = arg.contains(['|', '"', '\\'])
+        || arg.starts_with(char::is_whitespace)
+        || arg.ends_with(char::is_whitespace);
+    if !needs_quotes {
+        return arg.to_owned();
+    }
+
+    let mut quoted = String::with_capacity(arg.len() + 2);
+    quoted.push('"');
+    for c in arg.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use crate::parse::{join_args, split_args, split_leading_args, ParseError};
+
+    #[test]
+    fn plain_args_are_split_and_trimmed() {
+        assert_eq!(
+            split_args(" foo | bar baz |qux"),
+            Ok(vec![
+                "foo".to_owned(),
+                "bar baz".to_owned(),
+                "qux".to_owned()
+            ])
+        );
+        assert_eq!(split_args(""), Ok(vec!["".to_owned()]));
+    }
+
+    #[test]
+    fn quotes_and_escapes_are_literal() {
+        assert_eq!(
+            split_args(r#""  a | b  " | c\|d | say \"hi\""#),
+            Ok(vec![
+                "  a | b  ".to_owned(),
+                "c|d".to_owned(),
+                "say \"hi\"".to_owned()
+            ])
+        );
+    }
+
+    #[test]
+    fn malformed_input_fails() {
+        assert_eq!(
+            split_args(r#"foo | "bar"#),
+            Err(ParseError::UnterminatedQuote)
+        );
+        assert_eq!(split_args(r"foo\"), Err(ParseError::TrailingBackslash));
+    }
+
+    #[test]
+    fn leading_args_leave_the_rest_as_typed() {
+        assert_eq!(
+            split_leading_args(r#" "bob" | a | b "c "#, 1),
+            Ok((vec!["bob".to_owned()], r#"a | b "c"#))
+        );
+        assert_eq!(
+            split_leading_args("bob", 1),
+            Ok((vec!["bob".to_owned()], ""))
+        );
+        assert_eq!(split_leading_args(" a | b ", 0), Ok((Vec::new(), "a | b")));
+    }
+
+    proptest! {
+        #[test]
+        fn join_then_split_round_trips(args in prop::collection::vec(any::<String>(), 1..5)) {
+            prop_assert_eq!(split_args(&join_args(&args)), Ok(args));
+        }
+
+        #[test]
+        fn split_then_join_round_trips(str in any::<String>()) {
+            if let Ok(args) = split_args(&str) {
+                prop_assert_eq!(split_args(&join_args(&args)), Ok(args));
+            }
+        }
+
+        #[test]
+        fn plain_input_splits_like_before(str in "[a-z |]*") {
+            let expected = str.split("|").map(|s| s.trim().to_owned()).collect::<Vec<_>>();
+            prop_assert_eq!(split_args(&str), Ok(expected));
+        }
+    }
+}
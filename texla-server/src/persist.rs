@@ -0,0 +1,500 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::time::Duration;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::alias::Aliases;
+use crate::config::ServerConfig;
+use crate::login::Sessions;
+use crate::mail::{Mail, Mailbox};
+use crate::prelude::*;
+use crate::presence::Stashed;
+use crate::SpawnRoom;
+
+pub struct PersistPlugin;
+
+impl Plugin for PersistPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerConfig>()
+            .add_systems(Startup, load_world)
+            .add_systems(
+                Update,
+                (tick_autosave_timer, save_world.run_if(autosave_due)).chain(),
+            )
+            .add_systems(Last, save_world.run_if(on_event::<AppExit>));
+    }
+}
+
+#[derive(Resource, Debug)]
+struct AutosaveTimer(Timer);
+
+fn load_world(world: &mut World) {
+    let config = world.resource::<ServerConfig>();
+    let path = config.save_path.clone();
+    let autosave_interval = Duration::from_secs(config.autosave_secs);
+    let spawn_room_name = config.spawn_room.clone();
+
+    let spawn_room = if path.exists() {
+        let snapshot = fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|str| ron::from_str::<WorldSnapshot>(&str).map_err(|e| e.to_string()))
+            .and_then(|snapshot| snapshot.restore(world));
+        // Carrying on would save an empty world over the broken one at exit
+        let snapshot = match snapshot {
+            Ok(snapshot) => snapshot,
+            Err(e) => {
+                error!("Failed to load world from {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        };
+        info!("Loaded world from {}", path.display());
+        stash_players(world);
+        snapshot
+    } else {
+        info!("No world found at {}, creating a new one", path.display());
+        crate::spawn_voidroom(world)
+    };
+    let spawn_room = match spawn_room_name {
+        Some(name) => find_room(world, &name).unwrap_or_else(|| {
+            warn!("No room named {}, using the saved spawn room", name);
+            spawn_room
+        }),
+        None => spawn_room,
+    };
+
+    world.insert_resource(SpawnRoom(spawn_room));
+    world.insert_resource(AutosaveTimer(Timer::new(
+        autosave_interval,
+        TimerMode::Repeating,
+    )));
+}
+
+/// Nobody's connected yet, so nobody should be standing around in the world.
+fn stash_players(world: &mut World) {
+    let players = world
+        .query_filtered::<(Entity, &Parent), With<Player>>()
+        .iter(world)
+        .map(|(player, room)| (player, room.get()))
+        .collect::<Vec<_>>();
+    for (player, room) in players {
+        world
+            .entity_mut(player)
+            .remove_parent()
+            .insert(Stashed(room));
+    }
+}
+
+fn find_room(world: &mut World, name: &str) -> Option<Entity> {
+    world
+        .query_filtered::<(Entity, &Name), (With<Object>, Without<Player>, Without<Exit>)>()
+        .iter(world)
+        .find(|(_, room_name)| room_name.as_str() == name)
+        .map(|(entity, _)| entity)
+}
+
+fn tick_autosave_timer(time: Res<Time>, mut timer: ResMut<AutosaveTimer>) {
+    timer.0.tick(time.delta());
+}
+
+fn autosave_due(timer: Res<AutosaveTimer>) -> bool {
+    timer.0.just_finished()
+}
+
+fn save_world(world: &mut World) {
+    let path = world.resource::<ServerConfig>().save_path.clone();
+    let snapshot = WorldSnapshot::capture(world);
+
+    let result = ron::ser::to_string_pretty(&snapshot, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())
+        .and_then(|str| {
+            // Write to a sibling file first so a crash mid-write can't truncate the save
+            let tmp_path = path.with_extension("ron.tmp");
+            fs::write(&tmp_path, str)
+                .and_then(|_| fs::rename(&tmp_path, &path))
+                .map_err(|e| e.to_string())
+        });
+
+    match result {
+        Ok(()) => info!("Saved world to {}", path.display()),
+        Err(e) => error!("Failed to save world to {}: {}", path.display(), e),
+    }
+}
+
+/// Everything about the world that outlives a restart. Entities are referred to by their index
+/// in `objects`, since `Entity` ids aren't stable across runs.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WorldSnapshot {
+    pub spawn_room: usize,
+    pub objects: Vec<ObjectSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ObjectSnapshot {
+    pub name: Option<String>,
+    pub parent: Option<usize>,
+    pub properties: BTreeMap<String, String>,
+    pub player: Option<PlayerSnapshot>,
+    #[serde(default)]
+    pub exit: Option<ExitSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlayerSnapshot {
+    pub username: String,
+    #[serde(alias = "password")]
+    pub password_hash: String,
+    #[serde(default)]
+    pub roles: BTreeSet<Role>,
+    #[serde(default)]
+    pub mail: Vec<Mail>,
+    #[serde(default)]
+    pub sessions: Vec<String>,
+    #[serde(default)]
+    pub aliases: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExitSnapshot {
+    pub name: String,
+    pub aliases: Vec<String>,
+    pub destination: usize,
+}
+
+impl WorldSnapshot {
+    pub fn capture(world: &mut World) -> Self {
+        let mut objects = world.query::<(
+            Entity,
+            &Object,
+            Option<&Name>,
+            Option<&Parent>,
+            Option<&Player>,
+            Option<&Exit>,
+            Option<&Roles>,
+            Option<&Mailbox>,
+            Option<&Sessions>,
+            Option<&Aliases>,
+            Option<&Stashed>,
+        )>();
+        let mut entities = objects.iter(world).map(|(e, ..)| e).collect::<Vec<_>>();
+        entities.sort();
+        let indices = entities
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (*e, i))
+            .collect::<BTreeMap<_, _>>();
+
+        let spawn_room = world
+            .get_resource::<SpawnRoom>()
+            .and_then(|spawn_room| indices.get(&spawn_room.0).copied())
+            .unwrap_or_default();
+
+        let objects = entities
+            .iter()
+            .map(|entity| {
+                let (
+                    _,
+                    obj,
+                    name,
+                    parent,
+                    player,
+                    exit,
+                    roles,
+                    mailbox,
+                    sessions,
+                    aliases,
+                    stashed,
+                ) = objects.get(world, *entity).unwrap();
+                // Stashed players are saved in the room they'll return to, or the spawn room if
+                // that's gone, so that they aren't left nowhere
+                let parent = parent
+                    .map(|p| p.get())
+                    .or(stashed.map(|s| s.0))
+                    .and_then(|p| indices.get(&p).copied())
+                    .or(player.map(|_| spawn_room));
+                ObjectSnapshot {
+                    name: name.map(|n| n.to_string()),
+                    parent,
+                    properties: obj
+                        .properties
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect(),
+                    player: player.map(|player| PlayerSnapshot {
+                        username: player.username.clone(),
+                        password_hash: player.password_hash.clone(),
+                        roles: roles.map(|roles| roles.0.clone()).unwrap_or_default(),
+                        mail: mailbox.map(|mailbox| mailbox.0.clone()).unwrap_or_default(),
+                        sessions: sessions
+                            .map(|sessions| sessions.0.clone())
+                            .unwrap_or_default(),
+                        aliases: aliases.map(|aliases| aliases.0.clone()).unwrap_or_default(),
+                    }),
+                    exit: exit.and_then(|exit| {
+                        Some(ExitSnapshot {
+                            name: exit.name.clone(),
+                            aliases: exit.aliases.clone(),
+                            destination: *indices.get(&exit.destination)?,
+                        })
+                    }),
+                }
+            })
+            .collect();
+
+        Self {
+            spawn_room,
+            objects,
+        }
+    }
+
+    /// Spawns every object in the snapshot into `world` and returns the spawn room.
+    pub fn restore(self, world: &mut World) -> Result<Entity, String> {
+        if self.spawn_room >= self.objects.len() {
+            return Err(format!("Spawn room {} does not exist", self.spawn_room));
+        }
+        if let Some(parent) = self
+            .objects
+            .iter()
+            .filter_map(|obj| obj.parent)
+            .find(|parent| *parent >= self.objects.len())
+        {
+            return Err(format!("Parent {} does not exist", parent));
+        }
+        if let Some(index) = self
+            .objects
+            .iter()
+            .enumerate()
+            .position(|(index, obj)| obj.parent == Some(index))
+        {
+            return Err(format!("Object {} is its own parent", index));
+        }
+        // Following parents from anything should reach the top before running out of objects
+        if let Some(index) = (0..self.objects.len()).find(|index| {
+            std::iter::successors(Some(*index), |index| self.objects[*index].parent)
+                .nth(self.objects.len())
+                .is_some()
+        }) {
+            return Err(format!("Object {} is inside itself", index));
+        }
+        if let Some(destination) = self
+            .objects
+            .iter()
+            .filter_map(|obj| obj.exit.as_ref().map(|exit| exit.destination))
+            .find(|destination| *destination >= self.objects.len())
+        {
+            return Err(format!("Exit destination {} does not exist", destination));
+        }
+
+        let entities = self
+            .objects
+            .iter()
+            .map(|_| world.spawn_empty().id())
+            .collect::<Vec<_>>();
+
+        for (obj, entity) in self.objects.into_iter().zip(entities.iter()) {
+            let mut entity_mut = world.entity_mut(*entity);
+            entity_mut.insert(Object {
+                properties: obj.properties.into_iter().collect(),
+            });
+            if let Some(name) = obj.name {
+                entity_mut.insert(Name::new(name));
+            }
+            if let Some(player) = obj.player {
+                entity_mut.insert((
+                    Player {
+                        username: player.username,
+                        password_hash: player.password_hash,
+                    },
+                    Roles(player.roles),
+                    Mailbox(player.mail),
+                    Sessions(player.sessions),
+                    Aliases(player.aliases),
+                ));
+            }
+            if let Some(exit) = obj.exit {
+                entity_mut.insert(Exit {
+                    name: exit.name,
+                    aliases: exit.aliases,
+                    destination: entities[exit.destination],
+                });
+            }
+            if let Some(parent) = obj.parent {
+                entity_mut.set_parent(entities[parent]);
+            }
+        }
+
+        Ok(entities[self.spawn_room])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::alias::Aliases;
+    use crate::movement::Exit;
+    use crate::persist::{ObjectSnapshot, WorldSnapshot};
+    use crate::presence::Stashed;
+    use crate::{Object, Player, SpawnRoom};
+
+    fn round_trip(world: &mut World) -> World {
+        let snapshot = WorldSnapshot::capture(world);
+        let str = ron::to_string(&snapshot).unwrap();
+        let snapshot = ron::from_str::<WorldSnapshot>(&str).unwrap();
+
+        let mut restored = World::new();
+        let spawn_room = snapshot.restore(&mut restored).unwrap();
+        restored.insert_resource(SpawnRoom(spawn_room));
+        restored
+    }
+
+    #[test]
+    fn round_trip_keeps_rooms_and_players() {
+        let mut world = World::new();
+        let room = crate::spawn_voidroom(&mut world);
+        world.insert_resource(SpawnRoom(room));
+        world
+            .spawn((
+                Object::default(),
+                Player {
+                    username: "test".to_owned(),
+                    password_hash: "password".to_owned(),
+                },
+            ))
+            .set_parent(room);
+
+        let mut restored = round_trip(&mut world);
+
+        let spawn_room = restored.resource::<SpawnRoom>().0;
+        assert_eq!(
+            restored.get::<Name>(spawn_room).map(|n| n.as_str()),
+            Some("The Voidroom")
+        );
+        assert!(restored
+            .get::<Object>(spawn_room)
+            .unwrap()
+            .properties
+            .contains_key("description"));
+
+        let (player, parent) = restored.query::<(&Player, &Parent)>().single(&restored);
+        assert_eq!(player.username, "test");
+        assert_eq!(parent.get(), spawn_room);
+    }
+
+    #[test]
+    fn round_trip_moves_players_out_of_destroyed_rooms() {
+        let mut world = World::new();
+        let room = crate::spawn_voidroom(&mut world);
+        world.insert_resource(SpawnRoom(room));
+        let destroyed = world.spawn(Object::default()).id();
+        world.spawn((
+            Object::default(),
+            Player {
+                username: "test".to_owned(),
+                password_hash: "password".to_owned(),
+            },
+            Stashed(destroyed),
+        ));
+        world.despawn(destroyed);
+
+        let mut restored = round_trip(&mut world);
+
+        let spawn_room = restored.resource::<SpawnRoom>().0;
+        let (_, parent) = restored.query::<(&Player, &Parent)>().single(&restored);
+        assert_eq!(parent.get(), spawn_room);
+    }
+
+    #[test]
+    fn round_trip_keeps_aliases() {
+        let mut world = World::new();
+        let room = crate::spawn_voidroom(&mut world);
+        world.insert_resource(SpawnRoom(room));
+        let mut aliases = Aliases::default();
+        aliases.0.insert("gn".to_owned(), "go north".to_owned());
+        world
+            .spawn((
+                Object::default(),
+                Player {
+                    username: "test".to_owned(),
+                    password_hash: "password".to_owned(),
+                },
+                aliases,
+            ))
+            .set_parent(room);
+
+        let mut restored = round_trip(&mut world);
+
+        let aliases = restored.query::<&Aliases>().single(&restored);
+        assert_eq!(aliases.0.get("gn").map(String::as_str), Some("go north"));
+    }
+
+    #[test]
+    fn round_trip_keeps_exits() {
+        let mut world = World::new();
+        let room = crate::spawn_voidroom(&mut world);
+        world.insert_resource(SpawnRoom(room));
+        let other_room = world.spawn(Object::default()).id();
+        world
+            .spawn((
+                Object::default(),
+                Exit {
+                    name: "north".to_owned(),
+                    aliases: vec!["forward".to_owned()],
+                    destination: other_room,
+                },
+            ))
+            .set_parent(room);
+
+        let mut restored = round_trip(&mut world);
+
+        let spawn_room = restored.resource::<SpawnRoom>().0;
+        let (exit, parent) = restored.query::<(&Exit, &Parent)>().single(&restored);
+        assert_eq!(exit.name, "north");
+        assert_eq!(exit.aliases, vec!["forward".to_owned()]);
+        assert_eq!(parent.get(), spawn_room);
+        assert!(restored.get::<Object>(exit.destination).is_some());
+    }
+
+    #[test]
+    fn round_trip_ignores_non_objects() {
+        let mut world = World::new();
+        let room = crate::spawn_voidroom(&mut world);
+        world.insert_resource(SpawnRoom(room));
+        world.spawn(Name::new("Not an object"));
+
+        let mut restored = round_trip(&mut world);
+
+        assert_eq!(restored.query::<&Name>().iter(&restored).count(), 1);
+    }
+
+    #[test]
+    fn restoring_with_missing_spawn_room_fails() {
+        let snapshot = WorldSnapshot {
+            spawn_room: 3,
+            objects: vec![],
+        };
+
+        assert!(snapshot.restore(&mut World::new()).is_err());
+    }
+
+    #[test]
+    fn restoring_with_parent_loops_fails() {
+        let object = |parent| ObjectSnapshot {
+            parent,
+            ..default()
+        };
+
+        let own_parent = WorldSnapshot {
+            spawn_room: 0,
+            objects: vec![object(Some(0))],
+        };
+        assert!(own_parent.restore(&mut World::new()).is_err());
+
+        let looped = WorldSnapshot {
+            spawn_room: 0,
+            objects: vec![object(None), object(Some(2)), object(Some(1))],
+        };
+        assert!(looped.restore(&mut World::new()).is_err());
+    }
+}
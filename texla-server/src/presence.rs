@@ -0,0 +1,382 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::config::ServerConfig;
+use crate::interact::{look, LookBundle};
+use crate::markup::escape;
+use crate::prelude::*;
+use crate::SpawnRoom;
+
+pub struct PresencePlugin;
+
+impl Plugin for PresencePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerConfig>()
+            .add_event::<Disconnected>()
+            .add_observer(leave_world)
+            .add_systems(
+                Update,
+                (
+                    (handle_disconnects, enter_world).after(HandleCommandsSet),
+                    expire_link_dead,
+                ),
+            );
+    }
+}
+
+/// Sent when a connection closes. The connection is despawned once this frame's commands have
+/// been handled.
+#[derive(Event, Debug)]
+pub struct Disconnected {
+    pub conn: Entity,
+}
+
+/// A player whose connection dropped, left standing in the world until the timer runs out in case
+/// they reconnect.
+#[derive(Component, Debug)]
+pub struct LinkDead(pub Timer);
+
+/// A player who isn't in the world because nobody's playing them, and the room to put them back
+/// in when somebody does.
+#[derive(Component, Debug)]
+pub struct Stashed(pub Entity);
+
+fn handle_disconnects(
+    mut commands: Commands,
+    mut events: EventReader<Disconnected>,
+    conns: Query<&PlayerConnection>,
+    by_player: Res<ConnectionsByPlayer>,
+    players: Query<&Player>,
+    player_parents: Query<&Parent, With<Player>>,
+    room_conns: RoomConnections,
+    config: Res<ServerConfig>,
+) {
+    for Disconnected { conn } in events.read() {
+        if let Ok(player_conn) = conns.get(*conn) {
+            let player = player_conn.object;
+            let still_connected = by_player.of(player).iter().any(|other| other != conn);
+
+            if !still_connected && config.link_dead_secs > 0 {
+                commands.entity(player).insert(LinkDead(Timer::new(
+                    Duration::from_secs(config.link_dead_secs),
+                    TimerMode::Once,
+                )));
+                if let (Ok(room), Ok(player_info)) =
+                    (player_parents.get(player), players.get(player))
+                {
+                    announce(
+                        &mut commands,
+                        &room_conns,
+                        room.get(),
+                        player,
+                        format!("{} has lost their link.", escape(&player_info.username)),
+                    );
+                }
+            }
+        }
+
+        // Logs the player out too, which stashes them unless they were made link-dead above
+        if let Some(mut entity) = commands.get_entity(*conn) {
+            entity.despawn();
+        }
+    }
+}
+
+/// Takes a player out of the world when their last connection logs out or closes.
+fn leave_world(
+    trigger: Trigger<OnRemove, PlayerConnection>,
+    mut commands: Commands,
+    conns: Query<&PlayerConnection>,
+    by_player: Res<ConnectionsByPlayer>,
+    players: Query<&Player, Without<LinkDead>>,
+    player_parents: Query<&Parent, With<Player>>,
+    room_conns: RoomConnections,
+) {
+    let conn = trigger.entity();
+    let Ok(player_conn) = conns.get(conn) else {
+        return;
+    };
+    let player = player_conn.object;
+    if by_player.of(player).iter().any(|other| *other != conn) {
+        return;
+    }
+    let Ok(player_info) = players.get(player) else {
+        return;
+    };
+
+    stash(
+        &mut commands,
+        &room_conns,
+        &player_parents,
+        player,
+        player_info,
+    );
+}
+
+fn expire_link_dead(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut link_dead: Query<(Entity, &Player, &mut LinkDead)>,
+    player_parents: Query<&Parent, With<Player>>,
+    room_conns: RoomConnections,
+) {
+    for (player, player_info, mut timer) in link_dead.iter_mut() {
+        if !timer.0.tick(time.delta()).finished() {
+            continue;
+        }
+
+        commands.entity(player).remove::<LinkDead>();
+        stash(
+            &mut commands,
+            &room_conns,
+            &player_parents,
+            player,
+            player_info,
+        );
+    }
+}
+
+fn stash(
+    commands: &mut Commands,
+    room_conns: &RoomConnections,
+    player_parents: &Query<&Parent, With<Player>>,
+    player: Entity,
+    player_info: &Player,
+) {
+    let Ok(room) = player_parents.get(player) else {
+        return;
+    };
+
+    announce(
+        commands,
+        room_conns,
+        room.get(),
+        player,
+        format!("{} has left.", escape(&player_info.username)),
+    );
+    commands
+        .entity(player)
+        .remove_parent()
+        .insert(Stashed(room.get()));
+}
+
+/// Puts players who just logged in back where they were, or tells the room they've come back if
+/// they never left.
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn enter_world(
+    mut commands: Commands,
+    new_conns: Query<(Entity, &PlayerConnection), Added<PlayerConnection>>,
+    players: Query<(&Player, Option<&Stashed>, Option<&Parent>, Has<LinkDead>)>,
+    rooms: Query<(), With<Object>>,
+    spawn_room: Res<SpawnRoom>,
+    room_conns: RoomConnections,
+    looks: Query<LookBundle>,
+    exits: Query<&Exit>,
+) {
+    for (conn, player_conn) in new_conns.iter() {
+        let player = player_conn.object;
+        let Ok((player_info, stashed, parent, link_dead)) = players.get(player) else {
+            continue;
+        };
+
+        // Players saved nowhere, by older versions, start over in the spawn room
+        let returning = stashed
+            .map(|Stashed(room)| *room)
+            .or(parent.is_none().then_some(spawn_room.0));
+        if let Some(room) = returning {
+            // The room may have been destroyed while they were away
+            let room = if rooms.contains(room) {
+                room
+            } else {
+                spawn_room.0
+            };
+
+            announce(
+                &mut commands,
+                &room_conns,
+                room,
+                player,
+                format!("{} has arrived.", escape(&player_info.username)),
+            );
+            commands.entity(player).remove::<Stashed>().set_parent(room);
+            if let Ok(room_look) = looks.get(room) {
+                send_as(
+                    &mut commands,
+                    conn,
+                    MessageKind::Room,
+                    Ok(look(room_look, &exits)),
+                );
+            }
+        } else if link_dead {
+            commands.entity(player).remove::<LinkDead>();
+            if let Some(room) = parent {
+                announce(
+                    &mut commands,
+                    &room_conns,
+                    room.get(),
+                    player,
+                    format!("{} has reconnected.", escape(&player_info.username)),
+                );
+            }
+        }
+    }
+}
+
+/// Tells everyone in `room` but `player` something about `player`.
+fn announce(
+    commands: &mut Commands,
+    room_conns: &RoomConnections,
+    room: Entity,
+    player: Entity,
+    message: String,
+) {
+    let others = room_conns
+        .in_room(room)
+        .filter(|(_, other)| *other != player)
+        .map(|(conn, _)| conn)
+        .collect::<Vec<_>>();
+    broadcast_as(commands, others, MessageKind::Room, Ok(message));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+
+    use crate::config::ServerConfig;
+    use crate::interact::InteractPlugin;
+    use crate::login::LoginPlugin;
+    use crate::presence::{Disconnected, LinkDead, PresencePlugin, Stashed};
+    use crate::{Player, PlayerCommand, SpawnRoom};
+
+    fn presence_app(
+        link_dead_secs: u64,
+    ) -> (
+        App,
+        Entity,
+        std::sync::mpsc::Receiver<crate::ConnectionMessageEvent>,
+        Entity,
+    ) {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, PresencePlugin))
+            .init_resource::<Time>()
+            .insert_resource(ServerConfig {
+                link_dead_secs,
+                ..default()
+            });
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["watcher", "password"],
+            conn,
+        ));
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["leaver", "password"],
+            conns[0],
+        ));
+        crate::login::update_until_checked(&mut app);
+        while rx.try_recv().is_ok() {}
+
+        let world = app.world_mut();
+        let leaver = world
+            .query::<(Entity, &Player)>()
+            .iter(world)
+            .find(|(_, player)| player.username == "leaver")
+            .unwrap()
+            .0;
+        world.send_event(Disconnected { conn: conns[0] });
+        app.update();
+
+        (app, conn, rx, leaver)
+    }
+
+    #[test]
+    fn disconnecting_stashes_player() {
+        let (app, _conn, rx, leaver) = presence_app(0);
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "leaver has left.")));
+        assert!(app.world().get::<Parent>(leaver).is_none());
+        assert!(app.world().get::<Stashed>(leaver).is_some());
+    }
+
+    #[test]
+    fn link_dead_player_is_stashed_after_grace_period() {
+        let (mut app, _conn, rx, leaver) = presence_app(60);
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "leaver has lost their link.")));
+        assert!(app.world().get::<LinkDead>(leaver).is_some());
+        assert!(app.world().get::<Parent>(leaver).is_some());
+
+        app.world_mut()
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs(61));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "leaver has left.")));
+        assert!(app.world().get::<Stashed>(leaver).is_some());
+    }
+
+    #[test]
+    fn logging_in_unstashes_player() {
+        let (mut app, _conn, rx, leaver) = presence_app(0);
+        rx.try_recv().unwrap();
+
+        let new_conn = app.world_mut().spawn(crate::Connection).id();
+        app.world_mut().spawn(PlayerCommand::new(
+            "login",
+            vec!["leaver", "password"],
+            new_conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+
+        let spawn_room = app.world().resource::<SpawnRoom>().0;
+        assert_eq!(
+            app.world().get::<Parent>(leaver).map(|p| p.get()),
+            Some(spawn_room)
+        );
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == "leaver has arrived.")));
+    }
+
+    #[test]
+    fn commands_from_closed_connections_are_dropped() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, InteractPlugin, PresencePlugin))
+            .init_resource::<Time>();
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["test", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut().send_event(Disconnected { conn });
+        app.update();
+        app.world_mut()
+            .spawn(PlayerCommand::new("look", vec![], conn));
+        app.world_mut()
+            .spawn(PlayerCommand::new("nonsense", vec![], conn));
+        app.update();
+
+        assert!(app.world().get_entity(conn).is_err());
+        assert_eq!(
+            app.world_mut()
+                .query::<&PlayerCommand>()
+                .iter(app.world())
+                .count(),
+            0
+        );
+    }
+}
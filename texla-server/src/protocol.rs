@@ -0,0 +1,149 @@
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::markup::strip;
+use crate::prelude::*;
+
+pub struct ProtocolPlugin;
+
+impl Plugin for ProtocolPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, setup).add_systems(
+            Update,
+            (
+                (preprocess_commands::<ProtocolCommand>,).in_set(PreprocessCommandsSet),
+                (handle_protocol,).in_set(HandleCommandsSet),
+            ),
+        );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((CommandHandler::<ProtocolCommand>::new("protocol")
+        .with_args([Arg::new("format", ArgKind::Choice(&["text", "json"]))])
+        .with_summary("Switches between plain text and JSON messages.")
+        .with_help("Formats are `text` and `json`. Clients send this for you."),));
+}
+
+#[derive(Component, Default)]
+struct ProtocolCommand;
+
+/// How messages are written to a connection. Connections without one get plain text, with any
+/// markup stripped out.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum WireFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Serialize, Debug)]
+struct Envelope<'a> {
+    kind: MessageKind,
+    ok: bool,
+    body: &'a str,
+}
+
+pub fn encode(format: WireFormat, kind: MessageKind, message: &Result<String, String>) -> String {
+    match format {
+        WireFormat::Text => match message {
+            Ok(message) | Err(message) => strip(message),
+        },
+        WireFormat::Json => {
+            let (ok, body) = match message {
+                Ok(body) => (true, body),
+                Err(body) => (false, body),
+            };
+            serde_json::to_string(&Envelope { kind, ok, body }).unwrap()
+        }
+    }
+}
+
+fn handle_protocol(mut commands: Commands, comms: Query<&PlayerCommand, With<ProtocolCommand>>) {
+    for command in comms.iter() {
+        let format = match command.args.text("format") {
+            "json" => WireFormat::Json,
+            _ => WireFormat::Text,
+        };
+
+        commands.entity(command.conn).insert(format);
+        send_direct(
+            &mut commands,
+            command.conn,
+            MessageKind::Protocol,
+            Ok(command.args.text("format").to_owned()),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::protocol::{encode, ProtocolPlugin, WireFormat};
+    use crate::{MessageKind, PlayerCommand};
+
+    #[test]
+    fn text_is_unwrapped() {
+        assert_eq!(
+            encode(WireFormat::Text, MessageKind::Room, &Err("oops".to_owned())),
+            "oops"
+        );
+    }
+
+    #[test]
+    fn text_is_stripped_of_markup() {
+        assert_eq!(
+            encode(
+                WireFormat::Text,
+                MessageKind::Room,
+                &Ok("{bold}Void".to_owned())
+            ),
+            "Void"
+        );
+    }
+
+    #[test]
+    fn json_is_enveloped() {
+        assert_eq!(
+            encode(
+                WireFormat::Json,
+                MessageKind::Chat,
+                &Ok("say \"hi\"".to_owned())
+            ),
+            r#"{"kind":"chat","ok":true,"body":"say \"hi\""}"#
+        );
+        assert_eq!(
+            encode(WireFormat::Json, MessageKind::Text, &Err("oops".to_owned())),
+            r#"{"kind":"text","ok":false,"body":"oops"}"#
+        );
+    }
+
+    #[test]
+    fn handshake_sets_format() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(ProtocolPlugin);
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("protocol", vec!["json"], conn));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_ok() && msg.1 == MessageKind::Protocol));
+        assert_eq!(app.world().get::<WireFormat>(conn), Some(&WireFormat::Json));
+    }
+
+    #[test]
+    fn unknown_format_is_invalid() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins(ProtocolPlugin);
+
+        app.world_mut()
+            .spawn(PlayerCommand::parse("protocol xml", conn));
+        app.update();
+
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err_and(|msg| msg == "xml isn't text or json.")));
+        assert_eq!(app.world().get::<WireFormat>(conn), None);
+    }
+}
@@ -0,0 +1,223 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+
+use crate::config::ServerConfig;
+use crate::prelude::*;
+
+pub struct QueuePlugin;
+
+impl Plugin for QueuePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerConfig>()
+            .add_observer(receive_line)
+            .add_systems(Update, dispatch_queued.before(ResolveCommandsSet));
+    }
+}
+
+/// A line of text a connection sent, to be queued up as a command.
+#[derive(Event, Debug, Clone)]
+pub struct ReceivedLine(pub String);
+
+/// The lines a connection has sent that haven't been run yet, oldest first. One is run a tick, so
+/// that each connection's commands happen in the order they were sent.
+#[derive(Component, Debug, Default)]
+pub struct CommandQueue {
+    lines: VecDeque<String>,
+    /// How much of the burst allowance has been used up.
+    spent: f64,
+    warned: bool,
+    kicked: bool,
+}
+
+fn receive_line(
+    trigger: Trigger<ReceivedLine>,
+    mut commands: Commands,
+    mut queues: Query<&mut CommandQueue>,
+    config: Res<ServerConfig>,
+) {
+    let conn = trigger.entity();
+    let Ok(mut queue) = queues.get_mut(conn) else {
+        return;
+    };
+    if queue.kicked {
+        return;
+    }
+
+    let ReceivedLine(line) = trigger.event();
+    if line.chars().count() > config.max_line_len {
+        send(
+            &mut commands,
+            conn,
+            Err(format!(
+                "That's too long. Lines can be at most {} characters.",
+                config.max_line_len
+            )),
+        );
+        return;
+    }
+
+    if queue.lines.len() >= config.max_queued {
+        if queue.warned {
+            queue.lines.clear();
+            queue.kicked = true;
+            kick(
+                &mut commands,
+                conn,
+                "Disconnected for sending commands too fast.".to_owned(),
+            );
+        } else {
+            queue.warned = true;
+            send(
+                &mut commands,
+                conn,
+                Err(
+                    "You're sending commands too fast. Slow down or you'll be disconnected."
+                        .to_owned(),
+                ),
+            );
+        }
+        return;
+    }
+
+    queue.lines.push_back(line.clone());
+}
+
+/// Turns the oldest line in each queue into a command, as long as the connection isn't over its
+/// rate limit.
+pub fn dispatch_queued(
+    mut commands: Commands,
+    mut queues: Query<(Entity, &mut CommandQueue)>,
+    time: Res<Time>,
+    config: Res<ServerConfig>,
+) {
+    let burst = config.command_burst.max(1) as f64;
+    for (conn, mut queue) in queues.iter_mut() {
+        queue.spent = if config.command_rate > 0.0 {
+            (queue.spent - time.delta_secs_f64() * config.command_rate).max(0.0)
+        } else {
+            0.0
+        };
+
+        if queue.spent + 1.0 > burst {
+            continue;
+        }
+        let Some(line) = queue.lines.pop_front() else {
+            queue.warned = false;
+            continue;
+        };
+        queue.spent += 1.0;
+
+        commands.spawn(PlayerCommand::parse(&line, conn));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::prelude::*;
+
+    use crate::config::ServerConfig;
+    use crate::queue::{QueuePlugin, ReceivedLine};
+    use crate::utils::UtilsPlugin;
+    use crate::MessageKind;
+
+    fn queue_app(
+        config: ServerConfig,
+    ) -> (
+        App,
+        Entity,
+        std::sync::mpsc::Receiver<crate::ConnectionMessageEvent>,
+    ) {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((QueuePlugin, UtilsPlugin))
+            .init_resource::<Time>()
+            .insert_resource(config);
+        app.update();
+
+        (app, conn, rx)
+    }
+
+    fn receive(app: &mut App, conn: Entity, line: &str) {
+        app.world_mut()
+            .trigger_targets(ReceivedLine(line.to_owned()), conn);
+        app.world_mut().flush();
+    }
+
+    #[test]
+    fn commands_run_one_a_tick_in_order() {
+        let (mut app, conn, rx) = queue_app(ServerConfig::default());
+
+        for line in ["echo one", "echo two", "echo three"] {
+            receive(&mut app, conn, line);
+        }
+        for expected in ["one", "two", "three"] {
+            app.update();
+            assert!(rx
+                .try_recv()
+                .is_ok_and(|msg| msg.0.is_ok_and(|msg| msg == expected)));
+            assert!(rx.try_recv().is_err());
+        }
+    }
+
+    #[test]
+    fn commands_past_the_burst_wait_for_the_rate() {
+        let (mut app, conn, rx) = queue_app(ServerConfig {
+            command_rate: 1.0,
+            command_burst: 2,
+            ..default()
+        });
+
+        for _ in 0..3 {
+            receive(&mut app, conn, "echo hi");
+        }
+        for _ in 0..3 {
+            app.update();
+        }
+        assert_eq!(rx.try_iter().count(), 2);
+
+        app.world_mut()
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs(1));
+        app.update();
+        assert_eq!(rx.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn long_lines_are_refused() {
+        let (mut app, conn, rx) = queue_app(ServerConfig {
+            max_line_len: 8,
+            ..default()
+        });
+
+        receive(&mut app, conn, "echo far too long");
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn flooding_warns_then_disconnects() {
+        let (mut app, conn, rx) = queue_app(ServerConfig {
+            max_queued: 2,
+            ..default()
+        });
+
+        for _ in 0..3 {
+            receive(&mut app, conn, "echo hi");
+        }
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.0.is_err() && msg.1 == MessageKind::Text));
+
+        receive(&mut app, conn, "echo hi");
+        assert!(rx
+            .try_recv()
+            .is_ok_and(|msg| msg.1 == MessageKind::Disconnect));
+
+        app.update();
+        assert!(rx.try_recv().is_err());
+    }
+}
@@ -0,0 +1,452 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::config::ServerConfig;
+use crate::markup::escape;
+use crate::prelude::*;
+
+pub struct RolesPlugin;
+
+impl Plugin for RolesPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<ServerConfig>()
+            .add_systems(Startup, setup)
+            .add_systems(
+                Update,
+                (
+                    grant_configured_admins.before(PreprocessCommandsSet),
+                    (
+                        preprocess_commands::<GrantCommand>,
+                        preprocess_commands::<RevokeCommand>,
+                    )
+                        .in_set(PreprocessCommandsSet),
+                    (handle_grant, handle_revoke).in_set(HandleCommandsSet),
+                ),
+            );
+    }
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        CommandHandler::<GrantCommand>::new("grant")
+            .with_args([
+                Arg::new("player", ArgKind::Player),
+                Arg::new("role", ArgKind::Choice(&["builder", "admin"])),
+            ])
+            .with_summary("Gives a player a role.")
+            .with_help("Roles are builder and admin."),
+        RequiresLogin,
+        RequiresRole(Role::Admin),
+    ));
+    commands.spawn((
+        CommandHandler::<RevokeCommand>::new("revoke")
+            .with_args([
+                Arg::new("player", ArgKind::Player),
+                Arg::new("role", ArgKind::Choice(&["builder", "admin"])),
+            ])
+            .with_summary("Takes a role away from a player."),
+        RequiresLogin,
+        RequiresRole(Role::Admin),
+    ));
+}
+
+#[derive(Component, Default)]
+struct GrantCommand;
+
+#[derive(Component, Default)]
+struct RevokeCommand;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Player,
+    Builder,
+    Admin,
+}
+
+impl Role {
+    /// The role's name with an indefinite article, for error messages.
+    fn with_article(&self) -> String {
+        match self {
+            Role::Admin => format!("an {}", self),
+            _ => format!("a {}", self),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Role::Player => write!(f, "player"),
+            Role::Builder => write!(f, "builder"),
+            Role::Admin => write!(f, "admin"),
+        }
+    }
+}
+
+/// The roles granted to a player. Everyone implicitly has [`Role::Player`], and admins pass every
+/// role check.
+#[derive(Component, Debug, Default, Clone)]
+pub struct Roles(pub BTreeSet<Role>);
+
+impl Roles {
+    pub fn has(&self, role: Role) -> bool {
+        role == Role::Player || self.0.contains(&role) || self.0.contains(&Role::Admin)
+    }
+}
+
+/// Restricts a [`CommandHandler`] to players that have the role.
+#[derive(Component, Debug)]
+pub struct RequiresRole(pub Role);
+
+impl RequiresRole {
+    pub fn error(&self) -> String {
+        format!("You must be {} to do that.", self.0.with_article())
+    }
+}
+
+fn grant_configured_admins(
+    config: Res<ServerConfig>,
+    mut players: Query<(&Player, &mut Roles), Added<Player>>,
+) {
+    for (player, mut roles) in players.iter_mut() {
+        if config.admins.contains(&player.username) {
+            roles.0.insert(Role::Admin);
+        }
+    }
+}
+
+/// The role a command is about. Everyone has the player role, so it can't be granted or revoked.
+fn role_arg(command: &PlayerCommand) -> Role {
+    match command.args.text("role") {
+        "admin" => Role::Admin,
+        _ => Role::Builder,
+    }
+}
+
+fn handle_grant(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<GrantCommand>>,
+    mut players: Query<(Entity, &Player, &mut Roles)>,
+    by_player: Res<ConnectionsByPlayer>,
+) {
+    for command in comms.iter() {
+        let role = role_arg(command);
+
+        let player_entity = command.args.entity("player");
+        let Ok((_, player, mut roles)) = players.get_mut(player_entity) else {
+            continue;
+        };
+
+        if !roles.0.insert(role) {
+            send(
+                &mut commands,
+                command.conn,
+                Err(format!(
+                    "{} is already {}.",
+                    escape(&player.username),
+                    role.with_article()
+                )),
+            );
+            continue;
+        }
+
+        if let Some(&conn) = by_player.of(player_entity).first() {
+            send(
+                &mut commands,
+                conn,
+                Ok(format!("You have been granted the {} role.", role)),
+            );
+        }
+        send(
+            &mut commands,
+            command.conn,
+            Ok(format!("Granted {} to {}.", role, escape(&player.username))),
+        );
+    }
+}
+
+fn handle_revoke(
+    mut commands: Commands,
+    comms: Query<&PlayerCommand, With<RevokeCommand>>,
+    mut players: Query<(Entity, &Player, &mut Roles)>,
+    conns: Query<&PlayerConnection>,
+    by_player: Res<ConnectionsByPlayer>,
+    config: Res<ServerConfig>,
+) {
+    for command in comms.iter() {
+        let role = role_arg(command);
+
+        let player_entity = command.args.entity("player");
+        let Ok((_, player, mut roles)) = players.get_mut(player_entity) else {
+            continue;
+        };
+
+        let Ok(caller) = conns.get(command.conn).map(|conn| conn.object) else {
+            continue;
+        };
+        if player_entity == caller && role == Role::Admin {
+            send(
+                &mut commands,
+                command.conn,
+                Err("You can't revoke your own admin role.".to_owned()),
+            );
+            continue;
+        }
+
+        if role == Role::Admin && config.admins.contains(&player.username) {
+            send(
+                &mut commands,
+                command.conn,
+                Err(format!(
+                    "{} is an admin in the server's config.",
+                    escape(&player.username)
+                )),
+            );
+            continue;
+        }
+
+        if !roles.0.remove(&role) {
+            send(
+                &mut commands,
+                command.conn,
+                Err(format!(
+                    "{} is not {}.",
+                    escape(&player.username),
+                    role.with_article()
+                )),
+            );
+            continue;
+        }
+
+        if let Some(&conn) = by_player.of(player_entity).first() {
+            send(
+                &mut commands,
+                conn,
+                Ok(format!("Your {} role has been revoked.", role)),
+            );
+        }
+        send(
+            &mut commands,
+            command.conn,
+            Ok(format!(
+                "Revoked {} from {}.",
+                role,
+                escape(&player.username)
+            )),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
+
+    use crate::config::ServerConfig;
+    use crate::login::LoginPlugin;
+    use crate::roles::{Role, Roles, RolesPlugin};
+    use crate::{Player, PlayerCommand};
+
+    fn roles_of(app: &mut App, username: &str) -> Roles {
+        let world = app.world_mut();
+        world
+            .query::<(&Player, &Roles)>()
+            .iter(world)
+            .find(|(player, _)| player.username == username)
+            .map(|(_, roles)| roles.clone())
+            .unwrap()
+    }
+
+    #[test]
+    fn first_player_is_admin() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, RolesPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["first", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["second", "password"],
+            conns[0],
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        assert!(roles_of(&mut app, "first").has(Role::Admin));
+        assert!(!roles_of(&mut app, "second").has(Role::Admin));
+    }
+
+    #[test]
+    fn configured_admins_are_admin() {
+        let (mut app, conn, _rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, RolesPlugin));
+        app.insert_resource(ServerConfig {
+            admins: vec!["second".to_owned()],
+            ..default()
+        });
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["first", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["second", "password"],
+            conns[0],
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.update();
+
+        assert!(roles_of(&mut app, "second").has(Role::Admin));
+    }
+
+    #[test]
+    fn grant_and_revoke_work() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, RolesPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["first", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["second", "password"],
+            conns[0],
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("grant", vec!["second", "builder"], conn));
+        app.update();
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+        assert!(roles_of(&mut app, "second").has(Role::Builder));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "revoke",
+            vec!["second", "builder"],
+            conn,
+        ));
+        app.update();
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_ok()));
+        assert!(!roles_of(&mut app, "second").has(Role::Builder));
+    }
+
+    #[test]
+    fn granting_without_admin_fails() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, RolesPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["first", "password"],
+            conns[0],
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["second", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("grant", vec!["second", "admin"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg
+            .0
+            .is_err_and(|msg| msg == "You must be an admin to do that.")));
+        assert!(!roles_of(&mut app, "second").has(Role::Admin));
+    }
+
+    #[test]
+    fn revoking_own_admin_fails() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, RolesPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["first", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("revoke", vec!["first", "admin"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        assert!(roles_of(&mut app, "first").has(Role::Admin));
+    }
+
+    #[test]
+    fn revoking_configured_admin_fails() {
+        let (mut app, conn, rx, conns) = crate::test_app::<1>();
+        app.add_plugins((LoginPlugin, RolesPlugin));
+        app.insert_resource(ServerConfig {
+            admins: vec!["second".to_owned()],
+            ..default()
+        });
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["first", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["second", "password"],
+            conns[0],
+        ));
+        crate::login::update_until_checked(&mut app);
+        app.update();
+        rx.try_recv().unwrap();
+
+        app.world_mut()
+            .spawn(PlayerCommand::new("revoke", vec!["second", "admin"], conn));
+        app.update();
+
+        assert!(rx.try_recv().is_ok_and(|msg| msg.0.is_err()));
+        assert!(roles_of(&mut app, "second").has(Role::Admin));
+    }
+
+    #[test]
+    fn granting_an_unknown_role_fails() {
+        let (mut app, conn, rx, _conns) = crate::test_app::<0>();
+        app.add_plugins((LoginPlugin, RolesPlugin));
+
+        app.world_mut().spawn(PlayerCommand::new(
+            "register",
+            vec!["first", "password"],
+            conn,
+        ));
+        crate::login::update_until_checked(&mut app);
+        rx.try_recv().unwrap();
+
+        for role in ["wizard", "player"] {
+            app.world_mut()
+                .spawn(PlayerCommand::new("grant", vec!["first", role], conn));
+            app.update();
+
+            assert!(rx.try_recv().is_ok_and(|msg| msg
+                .0
+                .is_err_and(|msg| msg == format!("{} isn't builder or admin.", role))));
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use bevy::prelude::*;
 
+use crate::markup::escape;
 use crate::prelude::*;
 
 pub struct UtilsPlugin;
@@ -17,7 +18,9 @@ impl Plugin for UtilsPlugin {
 }
 
 fn setup(mut commands: Commands) {
-    commands.spawn((CommandHandler::<EchoCommand>::new("echo"),));
+    commands.spawn((CommandHandler::<EchoCommand>::new("echo")
+        .with_args([Arg::new("text", ArgKind::Text).optional().variadic()])
+        .with_summary("Repeats each argument back on its own line."),));
 }
 
 #[derive(Component, Default)]
@@ -28,7 +31,9 @@ fn handle_echo(mut commands: Commands, comms: Query<&PlayerCommand, With<EchoCom
         send(
             &mut commands,
             command.conn,
-            Ok(command.inner.args.join("\n")),
+            Ok(escape(
+                &command.args.texts("text").collect::<Vec<_>>().join("\n"),
+            )),
         );
     }
 }
@@ -1,26 +1,35 @@
 use bevy::prelude::*;
 use bevy_ws_server::{Message, ReceiveError, WsConnection, WsListener};
+use tungstenite::protocol::frame::coding::CloseCode;
+use tungstenite::protocol::CloseFrame;
 
-use crate::{Connection, ConnectionMessageEvent, PlayerCommand, PreprocessCommandsSet};
+use crate::config::ServerConfig;
+use crate::login::redact_credentials;
+use crate::presence::Disconnected;
+use crate::protocol::{encode, WireFormat};
+use crate::queue::{dispatch_queued, ReceivedLine};
+use crate::{CloseConnectionEvent, Connection, ConnectionMessageEvent};
 
 pub struct WsPlugin;
 
 impl Plugin for WsPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins(bevy_ws_server::WsPlugin)
+        app.init_resource::<ServerConfig>()
+            .add_plugins(bevy_ws_server::WsPlugin)
             .add_systems(Startup, setup)
             .add_systems(
                 Update,
                 (observe_connections, receive_message)
                     .chain()
                     .after(bevy_ws_server::accept_ws_from_queue)
-                    .before(PreprocessCommandsSet),
+                    .before(dispatch_queued),
             );
     }
 }
 
-fn setup(listener: Res<WsListener>) {
-    listener.listen("127.0.0.1:8080");
+fn setup(listener: Res<WsListener>, config: Res<ServerConfig>) {
+    info!("Listening on {}", config.address());
+    listener.listen(config.address().as_str());
 }
 
 fn observe_connections(mut commands: Commands, listener: Query<Entity, Added<WsConnection>>) {
@@ -28,22 +37,28 @@ fn observe_connections(mut commands: Commands, listener: Query<Entity, Added<WsC
         commands
             .entity(entity)
             .insert(Connection)
-            .observe(send_message);
+            .observe(send_message)
+            .observe(close_connection);
     }
 }
 
-fn receive_message(mut commands: Commands, conns: Query<(Entity, &WsConnection)>) {
+fn receive_message(
+    mut commands: Commands,
+    mut disconnected: EventWriter<Disconnected>,
+    conns: Query<(Entity, &WsConnection)>,
+) {
     for (entity, conn) in conns.iter() {
         loop {
             match conn.receive() {
                 Ok(Message::Text(message)) => {
-                    debug!("{} <| {}", conn.id(), message);
-                    commands.spawn(PlayerCommand::from_str(message, entity));
+                    debug!("{} <| {}", conn.id(), redact_credentials(&message));
+                    commands.trigger_targets(ReceivedLine(message), entity);
                 }
                 Ok(_) => {}
                 Err(ReceiveError::Empty) => break,
                 Err(ReceiveError::Closed) => {
-                    commands.entity(entity).despawn();
+                    debug!("{} closed", conn.id());
+                    disconnected.send(Disconnected { conn: entity });
                     break;
                 }
             }
@@ -51,11 +66,26 @@ fn receive_message(mut commands: Commands, conns: Query<(Entity, &WsConnection)>
     }
 }
 
-fn send_message(trigger: Trigger<ConnectionMessageEvent>, conns: Query<&WsConnection>) {
-    let conn = conns.get(trigger.entity()).unwrap();
-    match &trigger.event().0 {
-        Ok(message) | Err(message) => {
-            conn.send(Message::Text(message.clone()));
-        }
+fn send_message(
+    trigger: Trigger<ConnectionMessageEvent>,
+    conns: Query<(&WsConnection, Option<&WireFormat>)>,
+) {
+    let (conn, format) = conns.get(trigger.entity()).unwrap();
+    let ConnectionMessageEvent(message, kind) = trigger.event();
+    conn.send(Message::Text(encode(
+        format.copied().unwrap_or_default(),
+        *kind,
+        message,
+    )));
+}
+
+/// Closes a connection with a policy close code, so clients know not to reconnect.
+fn close_connection(trigger: Trigger<CloseConnectionEvent>, conns: Query<&WsConnection>) {
+    if let Ok(conn) = conns.get(trigger.entity()) {
+        debug!("{} closing", conn.id());
+        conn.send(Message::Close(Some(CloseFrame {
+            code: CloseCode::Policy,
+            reason: trigger.event().0.clone().into(),
+        })));
     }
 }